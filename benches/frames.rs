@@ -0,0 +1,67 @@
+//! Frames per second on the bundled roms, presented to the null renderer
+//! and to the terminal renderer writing to nowhere, to tell the cost of
+//! emulating from the cost of drawing.
+//!
+//! cargo bench --bench frames
+
+use std::fs;
+use std::io;
+use std::time::{Duration, Instant};
+
+use chip8r::graphic::Display;
+use chip8r::renderer::{NullRenderer, Renderer};
+use chip8r::scheduler::Scheduler;
+use chip8r::Chip8;
+
+const FRAMES: u32 = 20_000;
+
+fn run(rom: &[u8], renderer: &mut impl Renderer) -> (u32, Duration) {
+    let mut chip8 = Chip8::new();
+    chip8.load_rom(rom).unwrap();
+    let mut scheduler = Scheduler::with_instructions_per_frame(10, 60);
+    let frame_duration = scheduler.frame_duration();
+
+    let start = Instant::now();
+    let mut frames = 0;
+    while frames < FRAMES {
+        match scheduler.run_for(&mut chip8, frame_duration) {
+            Ok(true) => renderer.present(chip8.framebuffer()),
+            Ok(false) => {}
+            Err(_) => break
+        }
+        frames += 1;
+    }
+    renderer.shutdown();
+    (frames, start.elapsed())
+}
+
+fn frames_per_second(frames: u32, elapsed: Duration) -> f64 {
+    f64::from(frames) / elapsed.as_secs_f64()
+}
+
+fn main() {
+    let mut roms: Vec<_> = fs::read_dir(concat!(env!("CARGO_MANIFEST_DIR"), "/roms"))
+        .unwrap()
+        .filter_map(Result::ok)
+        .map(|entry| entry.path())
+        .filter(|path| path.extension().is_some_and(|extension| extension == "rom"))
+        .collect();
+    roms.sort();
+
+    println!("{:<20} {:>14} {:>14} {:>10}", "rom", "null (fps)", "terminal (fps)", "presented");
+    for path in roms {
+        let rom = fs::read(&path).unwrap();
+        let mut null = NullRenderer::new();
+        let (frames, null_elapsed) = run(&rom, &mut null);
+        let (_, terminal_elapsed) = run(&rom, &mut Display::with_writer(io::sink(), 1));
+
+        println!(
+            "{:<20} {:>14.0} {:>14.0} {:>10}{}",
+            path.file_name().unwrap().to_string_lossy(),
+            frames_per_second(frames, null_elapsed),
+            frames_per_second(frames, terminal_elapsed),
+            null.frames(),
+            if frames < FRAMES { " (stopped on an error)" } else { "" }
+        );
+    }
+}
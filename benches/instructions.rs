@@ -0,0 +1,87 @@
+//! Instructions per second on the bundled roms, with and without the
+//! instruction cache. Only executed instructions are counted, not the steps
+//! stalled in an Fx0A key wait. Results vary by up to 20% between runs,
+//! compare medians of a few.
+//!
+//! cargo bench --bench instructions
+
+use std::fs;
+use std::time::{Duration, Instant};
+
+use chip8r::quirks::Quirks;
+use chip8r::Chip8;
+
+const INSTRUCTIONS: u64 = 2_000_000;
+// Timers are ticked every so often so roms waiting on DT make progress
+const INSTRUCTIONS_PER_TICK: u64 = 1_000;
+
+/// Runs `INSTRUCTIONS` instructions, returns how many ran and how long they
+/// took. Steps polling an Fx0A key wait are not instructions and are not
+/// counted, a key is pressed then released so the rom carries on.
+fn run(rom: &[u8], cache: bool) -> (u64, Duration) {
+    // No display wait, so DRW does not stall between timer ticks
+    let mut chip8 = Chip8::with_quirks(Quirks::SCHIP);
+    chip8.set_instruction_cache(cache);
+    chip8.load_rom(rom).unwrap();
+
+    let start = Instant::now();
+    let mut executed = 0;
+    let mut next_key = 0;
+    let mut held = None;
+    while executed < INSTRUCTIONS {
+        if chip8.cpu().key_wait.is_some() {
+            match held.take() {
+                Some(key) => chip8.set_key(key, false),
+                None => {
+                    chip8.set_key(next_key, true);
+                    held = Some(next_key);
+                    next_key = (next_key + 1) % 16;
+                }
+            }
+            if chip8.step().is_err() {
+                break;
+            }
+            continue;
+        }
+        if chip8.step().is_err() {
+            break;
+        }
+        executed += 1;
+        if executed % INSTRUCTIONS_PER_TICK == 0 {
+            chip8.tick_timers();
+        }
+    }
+    (executed, start.elapsed())
+}
+
+fn instructions_per_second(executed: u64, elapsed: Duration) -> f64 {
+    executed as f64 / elapsed.as_secs_f64()
+}
+
+fn main() {
+    let mut roms: Vec<_> = fs::read_dir(concat!(env!("CARGO_MANIFEST_DIR"), "/roms"))
+        .unwrap()
+        .filter_map(Result::ok)
+        .map(|entry| entry.path())
+        .filter(|path| path.extension().is_some_and(|extension| extension == "rom"))
+        .collect();
+    roms.sort();
+
+    println!("{:<20} {:>16} {:>16} {:>8}", "rom", "decode (ips)", "cached (ips)", "speedup");
+    for path in roms {
+        let rom = fs::read(&path).unwrap();
+        let (executed, decode_elapsed) = run(&rom, false);
+        let (_, cached_elapsed) = run(&rom, true);
+
+        let decode = instructions_per_second(executed, decode_elapsed);
+        let cached = instructions_per_second(executed, cached_elapsed);
+        println!(
+            "{:<20} {:>16.0} {:>16.0} {:>7.2}x{}",
+            path.file_name().unwrap().to_string_lossy(),
+            decode,
+            cached,
+            cached / decode,
+            if executed < INSTRUCTIONS { " (stopped on an error)" } else { "" }
+        );
+    }
+}
@@ -0,0 +1,626 @@
+use std::collections::{BTreeMap, HashMap};
+use std::fmt;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::cpu::{MEMORY_SIZE, PROGRAM_START};
+use crate::instructions::Instruction;
+use crate::opcodes;
+
+// Deepest chain of include files, guards against a file including itself
+const MAX_INCLUDE_DEPTH: usize = 16;
+
+// Longest chain of constants defined by other constants, guards against a
+// constant defined by itself
+const MAX_CONSTANT_DEPTH: usize = 64;
+
+/// An error pointing at the source that caused it, lines and columns start at 1.
+#[derive(Debug, Clone, PartialEq)]
+pub struct AsmError {
+    pub file: String,
+    pub line: usize,
+    pub column: usize,
+    pub message: String
+}
+
+impl fmt::Display for AsmError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}:{}:{}: {}", self.file, self.line, self.column, self.message)
+    }
+}
+
+/// The assembled rom, loaded at 0x200, and the address of every label.
+#[derive(Debug)]
+pub struct Assembly {
+    pub rom: Vec<u8>,
+    pub labels: BTreeMap<String, u16>
+}
+
+impl Assembly {
+    /// One `0x208 label` line per label, sorted by address.
+    pub fn symbols(&self) -> String {
+        let mut labels: Vec<_> = self.labels.iter().collect();
+        labels.sort_by_key(|(name, addr)| (**addr, name.to_string()));
+        labels.iter().map(|(name, addr)| format!("{:#05X} {}\n", addr, name)).collect()
+    }
+}
+
+#[derive(Debug, Clone)]
+struct Location {
+    file: String,
+    line: usize,
+    column: usize
+}
+
+impl Location {
+    fn error(&self, message: String) -> AsmError {
+        AsmError {
+            file: self.file.clone(),
+            line: self.line,
+            column: self.column,
+            message
+        }
+    }
+
+    fn at(&self, column: usize) -> Location {
+        Location {
+            column,
+            ..self.clone()
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+struct Operand {
+    text: String,
+    location: Location
+}
+
+enum Item {
+    Instruction { mnemonic: String, operands: Vec<Operand>, location: Location },
+    // db when width is 1, dw when it is 2
+    Data { width: usize, values: Vec<Operand> }
+}
+
+struct Assembler<'a> {
+    loader: &'a mut dyn FnMut(&str) -> Result<String, String>,
+    items: Vec<Item>,
+    labels: BTreeMap<String, u16>,
+    constants: HashMap<String, Operand>,
+    addr: usize,
+    errors: Vec<AsmError>
+}
+
+/// Assembles `source`, `file` is the name used in errors.
+/// `loader` returns the content of the files named by `include` directives,
+/// given relative to the directory of `file`. An include inside an included
+/// file is relative to that file.
+pub fn assemble(source: &str, file: &str, loader: &mut dyn FnMut(&str) -> Result<String, String>) -> Result<Assembly, Vec<AsmError>> {
+    let mut assembler = Assembler {
+        loader,
+        items: Vec::new(),
+        labels: BTreeMap::new(),
+        constants: HashMap::new(),
+        addr: usize::from(PROGRAM_START),
+        errors: Vec::new()
+    };
+
+    assembler.parse(source, file, Path::new(""), 0);
+    let rom = assembler.encode();
+
+    if assembler.errors.is_empty() {
+        Ok(Assembly {
+            rom,
+            labels: assembler.labels
+        })
+    } else {
+        Err(assembler.errors)
+    }
+}
+
+/// Assembles a file, includes are relative to its directory.
+pub fn assemble_file(path: &Path) -> Result<Assembly, Vec<AsmError>> {
+    let name = path.display().to_string();
+    let source = fs::read_to_string(path).map_err(|msg| {
+        vec![AsmError { file: name.clone(), line: 0, column: 0, message: msg.to_string() }]
+    })?;
+
+    let dir = path.parent().unwrap_or_else(|| Path::new("")).to_path_buf();
+    let mut loader = |include: &str| fs::read_to_string(dir.join(include)).map_err(|msg| msg.to_string());
+    assemble(&source, &name, &mut loader)
+}
+
+impl<'a> Assembler<'a> {
+    /// First pass: collects labels, constants and the size of every item.
+    /// `dir` is the directory of `file`, relative to the first file.
+    fn parse(&mut self, source: &str, file: &str, dir: &Path, depth: usize) {
+        for (line_idx, line) in source.lines().enumerate() {
+            let location = Location { file: file.to_string(), line: line_idx + 1, column: 1 };
+            let code = match line.find(';') {
+                Some(comment) => &line[..comment],
+                None => line
+            };
+            let mut words = Words::new(code);
+
+            // Labels, "name:" or Octo's ": name"
+            let mut word = words.next();
+            loop {
+                match word {
+                    Some((column, ":")) => match words.next() {
+                        Some((column, name)) => self.define_label(name, &location.at(column)),
+                        None => self.errors.push(location.at(column).error("missing label name after ':'".to_string()))
+                    },
+                    Some((column, name)) if name.ends_with(':') && name != ":const" => {
+                        self.define_label(&name[..name.len() - 1], &location.at(column));
+                    }
+                    _ => break
+                }
+                word = words.next();
+            }
+
+            let (column, first) = match word {
+                Some(word) => word,
+                None => continue
+            };
+
+            // Constants, "NAME = value", "NAME equ value" or Octo's ":const NAME value"
+            let constant = if first == ":const" {
+                words.next().map(|(_, name)| (column, name, words.rest()))
+            } else {
+                let (_, rest) = words.rest();
+                let mut definition = Words::new(rest);
+                match definition.next() {
+                    Some((_, keyword)) if keyword == "=" || keyword.eq_ignore_ascii_case("equ") => {
+                        let (offset, value) = definition.rest();
+                        Some((column, first, (words.offset() + offset, value)))
+                    }
+                    _ => None
+                }
+            };
+            if let Some((column, name, (value_column, value))) = constant {
+                self.define_constant(name, value.trim(), &location.at(column), &location.at(value_column));
+                continue;
+            }
+
+            let mnemonic = first.to_uppercase();
+            let (operands_column, operands) = words.rest();
+            let operands = split_operands(operands, operands_column, &location);
+
+            match mnemonic.as_str() {
+                "DB" | "DW" => {
+                    let width = if mnemonic == "DB" { 1 } else { 2 };
+                    if operands.is_empty() {
+                        self.errors.push(location.at(column).error(format!("{} needs at least one value", first)));
+                    }
+                    self.addr += width * operands.len();
+                    self.items.push(Item::Data { width, values: operands });
+                }
+                "INCLUDE" => self.include(&operands, &location.at(column), dir, depth),
+                _ => {
+                    self.addr += 2;
+                    self.items.push(Item::Instruction { mnemonic, operands, location: location.at(column) });
+                }
+            }
+        }
+    }
+
+    fn include(&mut self, operands: &[Operand], location: &Location, dir: &Path, depth: usize) {
+        let path: PathBuf = match operands {
+            [operand] => dir.join(operand.text.trim_matches('"')),
+            _ => return self.errors.push(location.error("include needs one file name".to_string()))
+        };
+        if depth >= MAX_INCLUDE_DEPTH {
+            return self.errors.push(location.error(format!("includes nested deeper than {}", MAX_INCLUDE_DEPTH)));
+        }
+
+        let name = path.to_string_lossy();
+        match (self.loader)(&name) {
+            Ok(source) => self.parse(&source, &name, path.parent().unwrap_or(dir), depth + 1),
+            Err(msg) => self.errors.push(location.error(format!("could not include {} - {}", name, msg)))
+        }
+    }
+
+    fn define_label(&mut self, name: &str, location: &Location) {
+        if !is_identifier(name) {
+            return self.errors.push(location.error(format!("invalid label name '{}'", name)));
+        }
+        if self.labels.contains_key(name) || self.constants.contains_key(name) {
+            return self.errors.push(location.error(format!("'{}' is already defined", name)));
+        }
+        self.labels.insert(name.to_string(), self.addr as u16);
+    }
+
+    fn define_constant(&mut self, name: &str, value: &str, location: &Location, value_location: &Location) {
+        if !is_identifier(name) {
+            return self.errors.push(location.error(format!("invalid constant name '{}'", name)));
+        }
+        if self.labels.contains_key(name) || self.constants.contains_key(name) {
+            return self.errors.push(location.error(format!("'{}' is already defined", name)));
+        }
+        if value.is_empty() {
+            return self.errors.push(location.error(format!("missing value for '{}'", name)));
+        }
+        self.constants.insert(name.to_string(), Operand { text: value.to_string(), location: value_location.clone() });
+    }
+
+    /// Second pass: resolves the operands and encodes every item.
+    fn encode(&mut self) -> Vec<u8> {
+        let mut rom = Vec::new();
+        let items = std::mem::take(&mut self.items);
+        for item in &items {
+            match item {
+                Item::Instruction { mnemonic, operands, location } => {
+                    let opcode = match self.instruction(mnemonic, operands, location) {
+                        Ok(instruction) => opcodes::encode(&instruction),
+                        Err(error) => {
+                            self.errors.push(error);
+                            0
+                        }
+                    };
+                    rom.extend_from_slice(&opcode.to_be_bytes());
+                }
+                Item::Data { width, values } => {
+                    for value in values {
+                        let max = if *width == 1 { 0xFF } else { 0xFFFF };
+                        let value = self.value(value, max).unwrap_or_else(|error| {
+                            self.errors.push(error);
+                            0
+                        });
+                        rom.extend_from_slice(&(value as u16).to_be_bytes()[2 - width..]);
+                    }
+                }
+            }
+        }
+
+        let max = MEMORY_SIZE - usize::from(PROGRAM_START);
+        if rom.len() > max {
+            self.errors.push(AsmError {
+                file: String::new(),
+                line: 0,
+                column: 0,
+                message: format!("program is {} bytes, the maximum is {}", rom.len(), max)
+            });
+        }
+        rom
+    }
+
+    fn instruction(&self, mnemonic: &str, operands: &[Operand], location: &Location) -> Result<Instruction, AsmError> {
+        use self::Arg::*;
+
+        let args: Vec<Arg> = operands.iter().map(Arg::parse).collect();
+        let byte = |idx: usize| self.value(&operands[idx], 0xFF).map(|value| value as u8);
+        let addr = |idx: usize| self.value(&operands[idx], 0xFFF).map(|value| value as u16);
+        let nibble = |idx: usize| self.value(&operands[idx], 0xF).map(|value| value as u8);
+
+        let instruction = match (mnemonic, args.as_slice()) {
+            ("CLS", []) => Instruction::Cls,
+            ("RET", []) => Instruction::Ret,
+            ("SYS", [Value]) => Instruction::Sys { addr: addr(0)? },
+            ("JP", [Value]) => Instruction::Jp { addr: addr(0)? },
+            ("JP", [Register(0), Value]) => Instruction::JpV0 { addr: addr(1)? },
+            ("CALL", [Value]) => Instruction::Call { addr: addr(0)? },
+            ("SE", [Register(x), Register(y)]) => Instruction::Se { x: *x, y: *y },
+            ("SE", [Register(x), Value]) => Instruction::SeByte { x: *x, kk: byte(1)? },
+            ("SNE", [Register(x), Register(y)]) => Instruction::Sne { x: *x, y: *y },
+            ("SNE", [Register(x), Value]) => Instruction::SneByte { x: *x, kk: byte(1)? },
+            ("LD", [Register(x), Register(y)]) => Instruction::Ld { x: *x, y: *y },
+            ("LD", [Register(x), Dt]) => Instruction::LdVxDt { x: *x },
+            ("LD", [Register(x), K]) => Instruction::LdVxK { x: *x },
+            ("LD", [Register(x), IndirectI]) => Instruction::LdVxI { x: *x },
+            ("LD", [Register(x), Value]) => Instruction::LdByte { x: *x, kk: byte(1)? },
+            ("LD", [I, Value]) => Instruction::LdI { addr: addr(1)? },
+            ("LD", [Dt, Register(x)]) => Instruction::LdDtVx { x: *x },
+            ("LD", [St, Register(x)]) => Instruction::LdStVx { x: *x },
+            ("LD", [F, Register(x)]) => Instruction::LdF { x: *x },
+            ("LD", [B, Register(x)]) => Instruction::LdB { x: *x },
+            ("LD", [IndirectI, Register(x)]) => Instruction::LdIVx { x: *x },
+            ("ADD", [Register(x), Register(y)]) => Instruction::Add { x: *x, y: *y },
+            ("ADD", [Register(x), Value]) => Instruction::AddByte { x: *x, kk: byte(1)? },
+            ("ADD", [I, Register(x)]) => Instruction::AddI { x: *x },
+            ("OR", [Register(x), Register(y)]) => Instruction::Or { x: *x, y: *y },
+            ("AND", [Register(x), Register(y)]) => Instruction::And { x: *x, y: *y },
+            ("XOR", [Register(x), Register(y)]) => Instruction::Xor { x: *x, y: *y },
+            ("SUB", [Register(x), Register(y)]) => Instruction::Sub { x: *x, y: *y },
+            ("SUBN", [Register(x), Register(y)]) => Instruction::Subn { x: *x, y: *y },
+            // Without Vy, shift Vx in place whichever the shift quirk
+            ("SHR", [Register(x)]) => Instruction::Shr { x: *x, y: *x },
+            ("SHR", [Register(x), Register(y)]) => Instruction::Shr { x: *x, y: *y },
+            ("SHL", [Register(x)]) => Instruction::Shl { x: *x, y: *x },
+            ("SHL", [Register(x), Register(y)]) => Instruction::Shl { x: *x, y: *y },
+            ("RND", [Register(x), Value]) => Instruction::Rnd { x: *x, kk: byte(1)? },
+            ("DRW", [Register(x), Register(y), Value]) => Instruction::Drw { x: *x, y: *y, n: nibble(2)? },
+            ("SKP", [Register(x)]) => Instruction::Skp { x: *x },
+            ("SKNP", [Register(x)]) => Instruction::Sknp { x: *x },
+            (_, _) if !MNEMONICS.contains(&mnemonic) => {
+                return Err(location.error(format!("unknown instruction '{}'", mnemonic)));
+            }
+            (_, _) => {
+                let operands: Vec<&str> = operands.iter().map(|operand| operand.text.as_str()).collect();
+                return Err(location.error(format!("invalid operands for {}: '{}'", mnemonic, operands.join(", "))));
+            }
+        };
+        Ok(instruction)
+    }
+
+    /// Resolves an expression of numbers, labels and constants added or
+    /// subtracted together, checking it fits in `0..=max`.
+    /// Negative values down to `-(max + 1) / 2` are stored as two's complement.
+    fn value(&self, operand: &Operand, max: i64) -> Result<i64, AsmError> {
+        let value = self.evaluate(operand, 0)?;
+        if value > max || value < -(max + 1) / 2 {
+            return Err(operand.location.error(format!("'{}' is {}, out of range for 0..={:#X}", operand.text, value, max)));
+        }
+        Ok(value & max)
+    }
+
+    fn evaluate(&self, operand: &Operand, depth: usize) -> Result<i64, AsmError> {
+        let text = operand.text.as_str();
+        let error = |message: String| operand.location.error(message);
+        if text.is_empty() {
+            return Err(error("missing value".to_string()));
+        }
+
+        let mut total: i64 = 0;
+        let mut sign: i64 = 1;
+        let mut term_start = 0;
+        let chars: Vec<(usize, char)> = text.char_indices().chain(std::iter::once((text.len(), '+'))).collect();
+        for (idx, c) in chars {
+            if c != '+' && c != '-' {
+                continue;
+            }
+            let term = text[term_start..idx].trim();
+            if term.is_empty() {
+                // Unary sign, only before the first term
+                if idx == text.len() || term_start != 0 {
+                    return Err(error(format!("invalid expression '{}'", text)));
+                }
+                sign = if c == '-' { -1 } else { 1 };
+                term_start = idx + 1;
+                continue;
+            }
+
+            let value = match parse_number(term) {
+                Some(value) => value,
+                None if depth < MAX_CONSTANT_DEPTH => match (self.labels.get(term), self.constants.get(term)) {
+                    (Some(addr), _) => i64::from(*addr),
+                    (None, Some(constant)) => self.evaluate(constant, depth + 1)?,
+                    (None, None) if is_identifier(term) => return Err(error(format!("undefined symbol '{}'", term))),
+                    (None, None) => return Err(error(format!("invalid value '{}'", term)))
+                },
+                None => return Err(error(format!("constant '{}' refers to itself", term)))
+            };
+            total = sign.checked_mul(value).and_then(|term| total.checked_add(term)).ok_or_else(|| error(format!("value out of range in '{}'", text)))?;
+            sign = if c == '-' { -1 } else { 1 };
+            term_start = idx + 1;
+        }
+        Ok(total)
+    }
+}
+
+const MNEMONICS: &[&str] = &[
+    "CLS", "RET", "SYS", "JP", "CALL", "SE", "SNE", "LD", "ADD", "OR", "AND", "XOR",
+    "SUB", "SUBN", "SHR", "SHL", "RND", "DRW", "SKP", "SKNP"
+];
+
+/// Kinds of operand, telling apart the forms of LD, ADD, SE...
+enum Arg {
+    Register(u8),
+    I,
+    IndirectI,
+    Dt,
+    St,
+    K,
+    F,
+    B,
+    Value
+}
+
+impl Arg {
+    fn parse(operand: &Operand) -> Arg {
+        let text = operand.text.to_uppercase();
+        match text.as_str() {
+            "I" => Arg::I,
+            "[I]" => Arg::IndirectI,
+            "DT" => Arg::Dt,
+            "ST" => Arg::St,
+            "K" => Arg::K,
+            "F" => Arg::F,
+            "B" => Arg::B,
+            _ if text.len() == 2 && text.starts_with('V') => match u8::from_str_radix(&text[1..], 16) {
+                Ok(register) => Arg::Register(register),
+                Err(_) => Arg::Value
+            },
+            _ => Arg::Value
+        }
+    }
+}
+
+/// Decimal, 0x/$/# hexadecimal or 0b/% binary.
+fn parse_number(text: &str) -> Option<i64> {
+    let lower = text.to_lowercase();
+    let (digits, radix) = if let Some(digits) = lower.strip_prefix("0x") {
+        (digits, 16)
+    } else if let Some(digits) = lower.strip_prefix('$').or_else(|| lower.strip_prefix('#')) {
+        (digits, 16)
+    } else if let Some(digits) = lower.strip_prefix("0b").or_else(|| lower.strip_prefix('%')) {
+        (digits, 2)
+    } else {
+        (lower.as_str(), 10)
+    };
+    if digits.is_empty() || !digits.chars().all(|c| c.is_digit(radix)) {
+        return None;
+    }
+    i64::from_str_radix(digits, radix).ok()
+}
+
+fn is_identifier(name: &str) -> bool {
+    let mut chars = name.chars();
+    match chars.next() {
+        Some(c) if c.is_ascii_alphabetic() || c == '_' => chars.all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '.'),
+        _ => false
+    }
+}
+
+fn split_operands(text: &str, column: usize, location: &Location) -> Vec<Operand> {
+    if text.trim().is_empty() {
+        return Vec::new();
+    }
+
+    let mut operands = Vec::new();
+    let mut start = 0;
+    for part in text.split(',') {
+        let leading = part.len() - part.trim_start().len();
+        operands.push(Operand {
+            text: part.trim().to_string(),
+            location: location.at(column + start + leading)
+        });
+        start += part.len() + 1;
+    }
+    operands
+}
+
+/// Whitespace separated words of a line, with their column.
+struct Words<'a> {
+    line: &'a str,
+    offset: usize
+}
+
+impl<'a> Words<'a> {
+    fn new(line: &'a str) -> Self {
+        Words { line, offset: 0 }
+    }
+
+    fn next(&mut self) -> Option<(usize, &'a str)> {
+        let rest = &self.line[self.offset..];
+        let start = self.offset + (rest.len() - rest.trim_start().len());
+        let rest = &self.line[start..];
+        let end = start + rest.find(char::is_whitespace).unwrap_or(rest.len());
+        self.offset = end;
+        if start == end {
+            None
+        } else {
+            Some((start + 1, &self.line[start..end]))
+        }
+    }
+
+    /// Column and text of whatever follows the words read so far.
+    fn rest(&self) -> (usize, &'a str) {
+        (self.offset + 1, &self.line[self.offset..])
+    }
+
+    fn offset(&self) -> usize {
+        self.offset
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::disassembler;
+
+    fn assemble_source(source: &str) -> Result<Assembly, Vec<AsmError>> {
+        assemble(source, "test.asm", &mut |name| Err(format!("no such file {}", name)))
+    }
+
+    #[test]
+    fn instructions_test() {
+        let assembly = assemble_source("
+            CLS
+            LD VA, 0x02     ; comment
+            ld i, #2EA
+            DRW VA, VB, 6
+            LD [I], V3
+            LD V3, [I]
+            SHR V1
+            JP V0, $300
+        ").unwrap();
+
+        assert_eq!(vec![0x00, 0xE0, 0x6A, 0x02, 0xA2, 0xEA, 0xDA, 0xB6, 0xF3, 0x55, 0xF3, 0x65, 0x81, 0x16, 0xB3, 0x00], assembly.rom);
+    }
+
+    #[test]
+    fn labels_and_constants_test() {
+        let assembly = assemble_source("
+            SPEED = 3
+            :const HEIGHT 5
+            start:
+                LD V0, SPEED + 1
+                CALL draw
+                JP start
+            : draw
+                LD I, sprite
+                DRW V0, V0, HEIGHT
+                RET
+            sprite: db %11110000, 0x90
+                    dw 0x1234
+        ").unwrap();
+
+        assert_eq!(vec![
+            0x60, 0x04, 0x22, 0x06, 0x12, 0x00,
+            0xA2, 0x0C, 0xD0, 0x05, 0x00, 0xEE,
+            0xF0, 0x90, 0x12, 0x34
+        ], assembly.rom);
+        assert_eq!("0x200 start\n0x206 draw\n0x20C sprite\n", assembly.symbols());
+    }
+
+    #[test]
+    fn errors_test() {
+        let errors = assemble_source("LD V0, 0x100\n  JP nowhere\nFOO V1\nSE I, 2").unwrap_err();
+
+        assert_eq!(vec![
+            "test.asm:1:8: '0x100' is 256, out of range for 0..=0xFF",
+            "test.asm:2:6: undefined symbol 'nowhere'",
+            "test.asm:3:1: unknown instruction 'FOO'",
+            "test.asm:4:1: invalid operands for SE: 'I, 2'"
+        ], errors.iter().map(|error| error.to_string()).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn overflow_test() {
+        let errors = assemble_source("BIG = 0x7FFFFFFFFFFFFFFF\nLD V0, BIG + 1\nLD V1, -BIG - BIG").unwrap_err();
+
+        assert_eq!(vec![
+            "test.asm:2:8: value out of range in 'BIG + 1'",
+            "test.asm:3:8: value out of range in '-BIG - BIG'"
+        ], errors.iter().map(|error| error.to_string()).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn include_test() {
+        let mut loader = |name: &str| match name {
+            "sprites.asm" => Ok("sprite: db 0xFF".to_string()),
+            _ => Err("not found".to_string())
+        };
+        let assembly = assemble("LD I, sprite\ninclude \"sprites.asm\"", "main.asm", &mut loader).unwrap();
+        assert_eq!(vec![0xA2, 0x02, 0xFF], assembly.rom);
+
+        let mut loader = |_: &str| Ok("include \"self.asm\"".to_string());
+        assert!(assemble("include \"self.asm\"", "main.asm", &mut loader).is_err());
+    }
+
+    #[test]
+    fn nested_include_test() {
+        // lib/sprites.asm includes data.asm from its own directory
+        let mut loader = |name: &str| match name {
+            "lib/sprites.asm" => Ok("include \"data.asm\"".to_string()),
+            "lib/data.asm" => Ok("sprite: db 0xFF".to_string()),
+            _ => Err("not found".to_string())
+        };
+        let assembly = assemble("LD I, sprite\ninclude \"lib/sprites.asm\"", "main.asm", &mut loader).unwrap();
+        assert_eq!(vec![0xA2, 0x02, 0xFF], assembly.rom);
+
+        let errors = assemble("CLS\n  include \"lib/sprites.asm\"", "main.asm", &mut |_| Err("not found".to_string())).unwrap_err();
+        assert_eq!("main.asm:2:3: could not include lib/sprites.asm - not found", errors[0].to_string());
+
+        let errors = assemble_source("A = B\nB = A\nLD V0, A").unwrap_err();
+        assert_eq!("test.asm:2:4: constant 'A' refers to itself", errors[0].to_string());
+    }
+
+    #[test]
+    fn disassembly_round_trip_test() {
+        let rom = fs::read(concat!(env!("CARGO_MANIFEST_DIR"), "/roms/pong.rom")).unwrap();
+        let source = disassembler::disassemble(&rom).source();
+
+        assert_eq!(rom, assemble_source(&source).unwrap().rom);
+    }
+}
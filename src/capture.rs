@@ -0,0 +1,223 @@
+use std::io::Write;
+use std::path::Path;
+
+use crate::framebuffer::Framebuffer;
+use crate::palette::Palette;
+
+pub const FORMAT_NAMES: &[&str] = &["png", "ppm", "pbm"];
+
+/// Image pixels per screen pixel, unless configured
+pub const DEFAULT_SCALE: u16 = 4;
+
+/// Largest scale, each screenshot and GIF frame holds a byte per pixel,
+/// 2 MB at this scale and a quadratic growth past it
+pub const MAX_SCALE: u16 = 32;
+
+/// Image formats screenshots are written in
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ImageFormat {
+    Png,
+    // Binary netpbm formats, written without any dependency
+    Ppm,
+    // Monochrome, the palette is ignored and pixels that are on are black
+    Pbm
+}
+
+impl ImageFormat {
+    pub fn from_name(name: &str) -> Option<ImageFormat> {
+        match name.trim().to_lowercase().as_str() {
+            "png" => Some(ImageFormat::Png),
+            "ppm" => Some(ImageFormat::Ppm),
+            "pbm" => Some(ImageFormat::Pbm),
+            _ => None
+        }
+    }
+
+    /// The format from the extension of `path`.
+    pub fn from_path(path: &Path) -> Option<ImageFormat> {
+        path.extension().and_then(|extension| ImageFormat::from_name(&extension.to_string_lossy()))
+    }
+
+    pub fn extension(&self) -> &'static str {
+        match self {
+            ImageFormat::Png => "png",
+            ImageFormat::Ppm => "ppm",
+            ImageFormat::Pbm => "pbm"
+        }
+    }
+}
+
+/// Palette index of every pixel of the image, row by row, each pixel of
+/// the screen `scale` by `scale` pixels. `scale` is at most `MAX_SCALE`.
+fn indexed_pixels(screen: &Framebuffer, scale: u16) -> Vec<u8> {
+    let (width, height) = (screen.width() * scale, screen.height() * scale);
+    let mut pixels = Vec::with_capacity(usize::from(width) * usize::from(height));
+    for y in 0..height {
+        for x in 0..width {
+            pixels.push(u8::from(screen.get(x / scale, y / scale)));
+        }
+    }
+    pixels
+}
+
+/// The screen as an image file, `scale` is at most `MAX_SCALE`.
+pub fn screenshot(screen: &Framebuffer, palette: &Palette, scale: u16, format: ImageFormat) -> Vec<u8> {
+    let (width, height) = (screen.width() * scale, screen.height() * scale);
+    let pixels = indexed_pixels(screen, scale);
+    let rgb = palette.rgb();
+
+    let mut image = Vec::new();
+    match format {
+        ImageFormat::Png => {
+            let mut encoder = png::Encoder::new(&mut image, width.into(), height.into());
+            encoder.set_color(png::ColorType::Indexed);
+            encoder.set_depth(png::BitDepth::Eight);
+            encoder.set_palette(rgb.concat());
+            // Writing to memory cannot fail
+            let mut writer = encoder.write_header().unwrap();
+            writer.write_image_data(&pixels).unwrap();
+        }
+        ImageFormat::Ppm => {
+            write!(image, "P6\n{} {}\n255\n", width, height).unwrap();
+            for idx in pixels {
+                image.extend_from_slice(&rgb[usize::from(idx)]);
+            }
+        }
+        ImageFormat::Pbm => {
+            write!(image, "P4\n{} {}\n", width, height).unwrap();
+            // Rows are padded to whole bytes, leftmost pixel in the high bit
+            for row in pixels.chunks(usize::from(width)) {
+                for byte in row.chunks(8) {
+                    let bits = byte.iter().enumerate().fold(0u8, |bits, (bit, idx)| bits | (u8::from(*idx != 0) << (7 - bit)));
+                    image.push(bits);
+                }
+            }
+        }
+    }
+    image
+}
+
+/// Records frames to an animated GIF, looping forever.
+///
+/// Identical frames in a row are merged into one longer frame, so a
+/// recording mostly costs the frames where the screen changed.
+pub struct GifRecorder<W: Write> {
+    encoder: gif::Encoder<W>,
+    scale: u16,
+    fps: u32,
+    // Frames recorded so far, the time of a frame is frames / fps
+    frames: u64,
+    // The frame not written yet, with the number of the frame it started on
+    pending: Option<(Framebuffer, u64)>
+}
+
+impl<W: Write> GifRecorder<W> {
+    /// Starts a GIF of `fps` frames per second in `out`.
+    pub fn new(out: W, palette: &Palette, scale: u16, fps: u32) -> Result<Self, String> {
+        if scale > MAX_SCALE {
+            return Err(format!("the scale is {}, the largest supported is {}", scale, MAX_SCALE));
+        }
+        let (width, height) = (crate::graphic::WIDTH * scale, crate::graphic::HEIGHT * scale);
+        let mut encoder = gif::Encoder::new(out, width, height, &palette.rgb().concat()).map_err(|error| error.to_string())?;
+        encoder.set_repeat(gif::Repeat::Infinite).map_err(|error| error.to_string())?;
+        Ok(GifRecorder { encoder, scale, fps, frames: 0, pending: None })
+    }
+
+    /// Adds the next frame, `1 / fps` seconds after the previous one.
+    pub fn add_frame(&mut self, screen: &Framebuffer) -> Result<(), String> {
+        if !self.pending.is_some_and(|(pending, _)| pending == *screen) {
+            self.flush()?;
+            self.pending = Some((*screen, self.frames));
+        }
+        self.frames += 1;
+        Ok(())
+    }
+
+    /// Writes the last frame and the end of the GIF.
+    pub fn finish(mut self) -> Result<W, String> {
+        self.flush()?;
+        self.encoder.into_inner().map_err(|error| error.to_string())
+    }
+
+    fn flush(&mut self) -> Result<(), String> {
+        let (screen, start) = match self.pending.take() {
+            Some(pending) => pending,
+            None => return Ok(())
+        };
+        // GIF delays are in hundredths of a second, rounding the time of
+        // every frame keeps the total in step
+        let centiseconds = |frame: u64| (frame * 100 + u64::from(self.fps) / 2) / u64::from(self.fps);
+        let delay = (centiseconds(self.frames) - centiseconds(start)).min(u64::from(u16::MAX)) as u16;
+
+        let (width, height) = (screen.width() * self.scale, screen.height() * self.scale);
+        let mut frame = gif::Frame::from_indexed_pixels(width, height, indexed_pixels(&screen, self.scale), None);
+        frame.delay = delay;
+        self.encoder.write_frame(&frame).map_err(|error| error.to_string())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn screen() -> Framebuffer {
+        let mut screen = Framebuffer::new();
+        screen.set(0, 0, true);
+        screen.set(9, 0, true);
+        screen
+    }
+
+    #[test]
+    fn netpbm_test() {
+        let ppm = screenshot(&screen(), &Palette::AMBER, 2, ImageFormat::Ppm);
+        let header = b"P6\n128 64\n255\n";
+        assert_eq!(header, &ppm[..header.len()]);
+        assert_eq!(header.len() + 128 * 64 * 3, ppm.len());
+        assert_eq!([0xff, 0xb0, 0x00, 0xff, 0xb0, 0x00, 0x1a, 0x0d, 0x00], ppm[header.len()..header.len() + 9]);
+
+        let pbm = screenshot(&screen(), &Palette::AMBER, 1, ImageFormat::Pbm);
+        let header = b"P4\n64 32\n";
+        assert_eq!(header, &pbm[..header.len()]);
+        assert_eq!(header.len() + 8 * 32, pbm.len());
+        assert_eq!([0x80, 0x40, 0x00], pbm[header.len()..header.len() + 3]);
+    }
+
+    #[test]
+    fn png_test() {
+        let png = screenshot(&screen(), &Palette::DEFAULT, 3, ImageFormat::Png);
+        let mut reader = png::Decoder::new(png.as_slice()).read_info().unwrap();
+        let mut pixels = vec![0; reader.output_buffer_size()];
+        let info = reader.next_frame(&mut pixels).unwrap();
+        assert_eq!((192, 96), (info.width, info.height));
+        assert_eq!([1, 1, 1, 0], pixels[..4]);
+        assert_eq!(Some(&[0xff, 0xff, 0xff][..]), reader.info().palette.as_deref().map(|palette| &palette[3..6]));
+    }
+
+    #[test]
+    fn gif_test() {
+        let mut recorder = GifRecorder::new(Vec::new(), &Palette::GREEN, 1, 60).unwrap();
+        // One second of the same screen, then a changed frame
+        for _ in 0..60 {
+            recorder.add_frame(&Framebuffer::new()).unwrap();
+        }
+        recorder.add_frame(&screen()).unwrap();
+        let gif = recorder.finish().unwrap();
+
+        let mut decoder = gif::DecodeOptions::new().read_info(gif.as_slice()).unwrap();
+        let mut delays = Vec::new();
+        while let Some(frame) = decoder.read_next_frame().unwrap() {
+            assert_eq!((64, 32), (frame.width, frame.height));
+            delays.push(frame.delay);
+        }
+        assert_eq!(vec![100, 2], delays);
+
+        assert!(GifRecorder::new(Vec::new(), &Palette::GREEN, MAX_SCALE + 1, 60).is_err());
+    }
+
+    #[test]
+    fn format_test() {
+        assert_eq!(Some(ImageFormat::Png), ImageFormat::from_path(Path::new("shots/pong.PNG")));
+        assert_eq!(Some(ImageFormat::Pbm), ImageFormat::from_path(Path::new("pong.pbm")));
+        assert_eq!(None, ImageFormat::from_path(Path::new("pong.gif")));
+    }
+}
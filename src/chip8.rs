@@ -0,0 +1,512 @@
+use crate::cache::InstructionCache;
+use crate::cpu::{CPU, MAX_STACK_SIZE, MEMORY_SIZE, PROGRAM_START};
+use crate::error::Chip8Error;
+use crate::framebuffer::Framebuffer;
+use crate::opcodes;
+use crate::quirks::Quirks;
+use crate::savestate::{self, RomHash, StateError};
+use crate::watchpoints::{WatchHit, Watchpoints};
+
+/// Headless CHIP-8 machine.
+///
+/// Owns the CPU state and exposes everything a
+/// frontend needs to drive it: loading a rom, executing instructions,
+/// ticking the timers, reading the screen and feeding key presses.
+/// It never touches the terminal.
+#[derive(Clone)]
+pub struct Chip8 {
+    cpu: CPU,
+    cache: Option<InstructionCache>,
+    rom_hash: RomHash
+}
+
+impl Chip8 {
+    pub fn new() -> Self {
+        Chip8 {
+            cpu: CPU::new(),
+            cache: None,
+            rom_hash: savestate::rom_hash(&[])
+        }
+    }
+
+    pub fn with_quirks(quirks: Quirks) -> Self {
+        let mut chip8 = Chip8::new();
+        chip8.set_quirks(quirks);
+        chip8
+    }
+
+    pub fn set_quirks(&mut self, quirks: Quirks) {
+        self.cpu.quirks = quirks;
+    }
+
+    pub fn quirks(&self) -> Quirks {
+        self.cpu.quirks
+    }
+
+    /// Makes Cxkk produce the same sequence on every run with the same seed.
+    pub fn set_seed(&mut self, seed: u64) {
+        self.cpu.rng.set_seed(seed);
+    }
+
+    /// Sets how many nested CALLs are allowed before a StackOverflow,
+    /// 16 by default.
+    /// Panics if `size` is 0 or greater than `MAX_STACK_SIZE`.
+    pub fn set_stack_size(&mut self, size: usize) {
+        assert!(size > 0 && size <= MAX_STACK_SIZE, "stack size must be between 1 and {}", MAX_STACK_SIZE);
+        self.cpu.stack_size = size as u8;
+    }
+
+    /// Keeps decoded instructions around instead of decoding each opcode
+    /// every time it is executed. Off by default.
+    pub fn set_instruction_cache(&mut self, enabled: bool) {
+        self.cache = if enabled {
+            Some(InstructionCache::new())
+        } else {
+            None
+        };
+    }
+
+    pub fn instruction_cache(&self) -> Option<&InstructionCache> {
+        self.cache.as_ref()
+    }
+
+    /// Copies the rom into memory at 0x200, where programs start.
+    pub fn load_rom(&mut self, rom: &[u8]) -> Result<(), Chip8Error> {
+        let start = usize::from(PROGRAM_START);
+        let max = MEMORY_SIZE - start;
+        if rom.len() > max {
+            return Err(Chip8Error::RomTooLarge { size: rom.len(), max });
+        }
+        self.cpu.memory[start..start + rom.len()].copy_from_slice(rom);
+        self.rom_hash = savestate::rom_hash(rom);
+        if let Some(cache) = &mut self.cache {
+            cache.clear();
+        }
+        Ok(())
+    }
+
+    /// SHA-1 of the loaded rom.
+    pub fn rom_hash(&self) -> RomHash {
+        self.rom_hash
+    }
+
+    /// Executes a single instruction.
+    /// Returns true when the screen has changed.
+    /// On error the PC is left on the faulting instruction.
+    pub fn step(&mut self) -> Result<bool, Chip8Error> {
+        self.cpu.watch_hit = None;
+        // Timers keep running while Fx0A waits, only the CPU stalls
+        if self.cpu.key_wait.is_some() {
+            self.cpu.poll_key_wait();
+            return Ok(false);
+        }
+        let update_screen = match &mut self.cache {
+            Some(cache) => {
+                let instruction = cache.fetch(&self.cpu)?;
+                let result = opcodes::execute(&mut self.cpu, &instruction);
+                // Self-modifying code, drop whatever was just overwritten
+                if let Some((start, end)) = self.cpu.memory_writes.take() {
+                    cache.invalidate(start, end);
+                }
+                result?
+            }
+            None => {
+                let opcode = self.cpu.get_next_opcode()?;
+                opcodes::execute_op_code(&mut self.cpu, &opcode)?
+            }
+        };
+        // Jumps leave the pc 2 before their target, which wraps for 0x000
+        self.cpu.pc = self.cpu.pc.wrapping_add(2);
+        Ok(update_screen)
+    }
+
+    /// Executes `n` instructions.
+    /// Returns true when the screen has changed during any of them.
+    pub fn run_cycles(&mut self, n: usize) -> Result<bool, Chip8Error> {
+        let mut update_screen = false;
+        for _ in 0..n {
+            update_screen |= self.step()?;
+        }
+        Ok(update_screen)
+    }
+
+    /// Decrements the delay and sound timers, to be called at 60 Hz.
+    /// This is also the vertical blank DRW waits for with the display wait quirk.
+    pub fn tick_timers(&mut self) {
+        self.cpu.update_timers();
+    }
+
+    /// The 64x32 screen.
+    pub fn framebuffer(&self) -> &Framebuffer {
+        &self.cpu.screen
+    }
+
+    /// Updates the state of key `k` (0x0 - 0xF).
+    pub fn set_key(&mut self, k: u8, pressed: bool) {
+        let mask = 1 << (k & 0xF);
+        if pressed {
+            self.cpu.keys |= mask;
+        } else {
+            self.cpu.keys &= !mask;
+        }
+    }
+
+    /// Keys held down, bit n set for key n.
+    pub fn pressed_keys(&self) -> u16 {
+        self.cpu.keys
+    }
+
+    pub fn watchpoints(&self) -> &Watchpoints {
+        &self.cpu.watchpoints
+    }
+
+    pub fn watchpoints_mut(&mut self) -> &mut Watchpoints {
+        &mut self.cpu.watchpoints
+    }
+
+    /// The watchpoint triggered by the last instruction executed, if any.
+    pub fn watch_hit(&self) -> Option<WatchHit> {
+        self.cpu.watch_hit
+    }
+
+    pub fn cpu(&self) -> &CPU {
+        &self.cpu
+    }
+
+    /// Copy of the whole machine state.
+    pub fn snapshot(&self) -> CPU {
+        self.cpu
+    }
+
+    /// Puts the machine back in a state taken with `snapshot`.
+    pub fn restore(&mut self, cpu: CPU) {
+        self.cpu = cpu;
+        if let Some(cache) = &mut self.cache {
+            cache.clear();
+        }
+    }
+
+    /// The machine state as a save state file, see `savestate::encode`.
+    pub fn save_state(&self) -> Vec<u8> {
+        savestate::encode(&self.cpu, &self.rom_hash)
+    }
+
+    /// Restores a state written by `save_state`, it must have been taken
+    /// with the rom currently loaded.
+    pub fn load_state(&mut self, bytes: &[u8]) -> Result<(), StateError> {
+        let cpu = savestate::decode(bytes, &self.rom_hash, &self.cpu)?;
+        self.restore(cpu);
+        Ok(())
+    }
+}
+
+impl Default for Chip8 {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn load_rom_test() {
+        let mut chip8 = Chip8::new();
+        chip8.load_rom(&[0x61, 0x2A]).unwrap();
+
+        assert_eq!(0x61, chip8.cpu().memory[0x200]);
+        assert_eq!(0x2A, chip8.cpu().memory[0x201]);
+        assert_eq!(4096, chip8.cpu().memory.len());
+    }
+
+    #[test]
+    fn step_test() {
+        let mut chip8 = Chip8::new();
+        // LD V1, 0x2A - LD I, 0x2D8
+        chip8.load_rom(&[0x61, 0x2A, 0xA2, 0xD8]).unwrap();
+
+        chip8.run_cycles(2).unwrap();
+
+        assert_eq!(0x2A, chip8.cpu().get_reg(1));
+        assert_eq!(0x2D8, chip8.cpu().i);
+        assert_eq!(0x204, chip8.cpu().pc);
+    }
+
+    #[test]
+    fn jump_to_zero_test() {
+        let mut chip8 = Chip8::new();
+        // JP 0x000
+        chip8.load_rom(&[0x10, 0x00]).unwrap();
+        chip8.step().unwrap();
+        assert_eq!(0x000, chip8.cpu().pc);
+
+        let mut chip8 = Chip8::new();
+        // CALL 0x000
+        chip8.load_rom(&[0x20, 0x00]).unwrap();
+        chip8.step().unwrap();
+        assert_eq!((0x000, 1), (chip8.cpu().pc, chip8.cpu().sp));
+
+        let mut chip8 = Chip8::new();
+        // JP V0, 0x000 with V0 = 0
+        chip8.load_rom(&[0xB0, 0x00]).unwrap();
+        chip8.step().unwrap();
+        assert_eq!(0x000, chip8.cpu().pc);
+    }
+
+    #[test]
+    fn draw_updates_framebuffer_test() {
+        let mut chip8 = Chip8::new();
+        // LD I, 0x000 (sprite "0") - DRW V0, V0, 5
+        chip8.load_rom(&[0xA0, 0x00, 0xD0, 0x05]).unwrap();
+
+        chip8.step().unwrap();
+        let update_screen = chip8.step().unwrap();
+
+        assert!(update_screen);
+        assert_eq!(0xF0 << 56, chip8.framebuffer().row(0));
+    }
+
+    #[test]
+    fn load_rom_too_large_test() {
+        let mut chip8 = Chip8::new();
+
+        assert_eq!(Err(Chip8Error::RomTooLarge { size: 3585, max: 3584 }), chip8.load_rom(&[0; 3585]));
+        assert_eq!(Ok(()), chip8.load_rom(&[0; 3584]));
+    }
+
+    #[test]
+    fn step_error_keeps_pc_test() {
+        let mut chip8 = Chip8::new();
+        // LD V1, 0x2A - RET
+        chip8.load_rom(&[0x61, 0x2A, 0x00, 0xEE]).unwrap();
+
+        assert_eq!(Err(Chip8Error::StackUnderflow), chip8.run_cycles(2));
+        assert_eq!(0x202, chip8.cpu().pc);
+    }
+
+    #[test]
+    fn snapshot_restore_test() {
+        let mut chip8 = Chip8::new();
+        // LD V1, 0x2A - ADD V1, 0x01
+        chip8.load_rom(&[0x61, 0x2A, 0x71, 0x01]).unwrap();
+        chip8.step().unwrap();
+
+        let snapshot = chip8.snapshot();
+        chip8.step().unwrap();
+        assert_eq!(0x2B, chip8.cpu().get_reg(1));
+
+        chip8.restore(snapshot);
+        assert_eq!(0x2A, chip8.cpu().get_reg(1));
+        assert_eq!(0x202, chip8.cpu().pc);
+    }
+
+    #[test]
+    fn instruction_cache_test() {
+        let mut chip8 = Chip8::new();
+        chip8.set_instruction_cache(true);
+        // LD V1, 0x01 - LD V0, 0x2A - LD I, 0x201 - LD [I], V0 - JP 0x200
+        chip8.load_rom(&[0x61, 0x01, 0x60, 0x2A, 0xA2, 0x01, 0xF0, 0x55, 0x12, 0x00]).unwrap();
+
+        chip8.run_cycles(5).unwrap();
+        assert_eq!(0x01, chip8.cpu().get_reg(1));
+        assert_eq!(0, chip8.instruction_cache().unwrap().hits());
+
+        // The first pass overwrote the low byte of the cached LD V1, 0x01
+        chip8.run_cycles(5).unwrap();
+        assert_eq!(0x2A, chip8.cpu().get_reg(1));
+
+        let cache = chip8.instruction_cache().unwrap();
+        assert_eq!(4, cache.hits());
+        assert_eq!(6, cache.misses());
+    }
+
+    #[test]
+    fn stack_size_test() {
+        let mut chip8 = Chip8::new();
+        chip8.set_stack_size(2);
+        // CALL 0x200
+        chip8.load_rom(&[0x22, 0x00]).unwrap();
+
+        assert_eq!(Err(Chip8Error::StackOverflow), chip8.run_cycles(3));
+        assert_eq!(2, chip8.cpu().sp);
+    }
+
+    #[test]
+    fn shift_quirk_test() {
+        // LD V1, 0x03 - LD V2, 0x10 - SHR V1, V2
+        let rom = [0x61, 0x03, 0x62, 0x10, 0x81, 0x26];
+
+        let mut chip8 = Chip8::with_quirks(Quirks::VIP);
+        chip8.load_rom(&rom).unwrap();
+        chip8.run_cycles(3).unwrap();
+        assert_eq!(0x08, chip8.cpu().get_reg(1));
+        assert_eq!(0, chip8.cpu().get_reg(0xF));
+
+        let mut chip8 = Chip8::with_quirks(Quirks::SCHIP);
+        chip8.load_rom(&rom).unwrap();
+        chip8.run_cycles(3).unwrap();
+        assert_eq!(0x01, chip8.cpu().get_reg(1));
+        assert_eq!(1, chip8.cpu().get_reg(0xF));
+    }
+
+    #[test]
+    fn memory_increment_quirk_test() {
+        // LD I, 0x300 - LD [I], V2
+        let rom = [0xA3, 0x00, 0xF2, 0x55];
+
+        let expected = [(Quirks::VIP, 0x303), (Quirks::CHIP48, 0x302), (Quirks::SCHIP, 0x300)];
+        for (quirks, i) in expected.iter() {
+            let mut chip8 = Chip8::with_quirks(*quirks);
+            chip8.load_rom(&rom).unwrap();
+            chip8.run_cycles(2).unwrap();
+            assert_eq!(*i, chip8.cpu().i);
+        }
+    }
+
+    #[test]
+    fn display_wait_quirk_test() {
+        // DRW V0, V0, 1 - DRW V0, V0, 1
+        let rom = [0xD0, 0x01, 0xD0, 0x01];
+
+        let mut chip8 = Chip8::with_quirks(Quirks::VIP);
+        chip8.load_rom(&rom).unwrap();
+        chip8.run_cycles(3).unwrap();
+        assert_eq!(0x202, chip8.cpu().pc);
+
+        chip8.tick_timers();
+        chip8.step().unwrap();
+        assert_eq!(0x204, chip8.cpu().pc);
+
+        let mut chip8 = Chip8::with_quirks(Quirks::SCHIP);
+        chip8.load_rom(&rom).unwrap();
+        chip8.run_cycles(2).unwrap();
+        assert_eq!(0x204, chip8.cpu().pc);
+    }
+
+    #[test]
+    fn wait_for_key_test() {
+        let mut chip8 = Chip8::new();
+        // LD V3, K - ADD V3, 0x01
+        chip8.load_rom(&[0xF3, 0x0A, 0x73, 0x01]).unwrap();
+
+        chip8.run_cycles(3).unwrap();
+        assert!(chip8.cpu().key_wait.is_some());
+        assert_eq!(0x202, chip8.cpu().pc);
+
+        // Completes on the release, like the VIP
+        chip8.set_key(0x7, true);
+        chip8.run_cycles(3).unwrap();
+        assert!(chip8.cpu().key_wait.is_some());
+        chip8.set_key(0x7, false);
+        chip8.step().unwrap();
+        assert_eq!(0x7, chip8.cpu().get_reg(3));
+
+        chip8.step().unwrap();
+        assert_eq!(0x8, chip8.cpu().get_reg(3));
+        assert_eq!(0x204, chip8.cpu().pc);
+    }
+
+    #[test]
+    fn wait_for_key_on_press_quirk_test() {
+        let mut chip8 = Chip8::with_quirks(Quirks { key_on_press: true, ..Quirks::VIP });
+        // LD V3, K - JP 0x200
+        chip8.load_rom(&[0xF3, 0x0A, 0x12, 0x00]).unwrap();
+
+        chip8.step().unwrap();
+        chip8.set_key(0x7, true);
+        chip8.step().unwrap();
+        assert_eq!(0x7, chip8.cpu().get_reg(3));
+        assert_eq!(None, chip8.cpu().key_wait);
+
+        // Held keys are not read again
+        chip8.run_cycles(5).unwrap();
+        assert!(chip8.cpu().key_wait.is_some());
+        chip8.set_key(0x7, false);
+        chip8.set_key(0x2, true);
+        chip8.step().unwrap();
+        assert_eq!(0x2, chip8.cpu().get_reg(3));
+    }
+
+    #[test]
+    fn key_state_test() {
+        let mut chip8 = Chip8::new();
+        // SKP V0 - SKNP V1 - JP 0x200
+        chip8.load_rom(&[0xE0, 0x9E, 0xE1, 0xA1, 0x12, 0x00]).unwrap();
+        chip8.cpu.registers[1] = 0x1;
+
+        // Both keys held at once, SKP skips the SKNP
+        chip8.set_key(0x0, true);
+        chip8.set_key(0x1, true);
+        assert_eq!(0b11, chip8.pressed_keys());
+        chip8.step().unwrap();
+        assert_eq!(0x204, chip8.cpu().pc);
+
+        // Released keys stay released
+        chip8.set_key(0x0, false);
+        chip8.set_key(0x1, false);
+        chip8.run_cycles(2).unwrap();
+        assert_eq!(0x202, chip8.cpu().pc);
+        chip8.step().unwrap();
+        assert_eq!(0x206, chip8.cpu().pc);
+    }
+
+    #[test]
+    fn watchpoints_test() {
+        use crate::watchpoints::{Access, WatchTarget};
+
+        let mut chip8 = Chip8::new();
+        // LD I, 0x300 - LD V0, 0x2A - LD [I], V0 - LD V1, [I]
+        chip8.load_rom(&[0xA3, 0x00, 0x60, 0x2A, 0xF0, 0x55, 0xF1, 0x65]).unwrap();
+        chip8.watchpoints_mut().add(WatchTarget::Memory { start: 0x300, end: 0x301 }, Access::ReadWrite);
+        chip8.watchpoints_mut().add(WatchTarget::Register(1), Access::Write);
+
+        chip8.run_cycles(2).unwrap();
+        assert_eq!(None, chip8.watch_hit());
+
+        chip8.step().unwrap();
+        assert_eq!(Some(WatchHit { pc: 0x204, target: WatchTarget::Memory { start: 0x300, end: 0x300 }, access: Access::Write, old: 0x00, new: 0x2A }), chip8.watch_hit());
+
+        // I was moved to 0x301, its read comes before the write to V1
+        chip8.step().unwrap();
+        let hit = chip8.watch_hit().unwrap();
+        assert_eq!((0x206, Access::Read), (hit.pc, hit.access));
+        assert_eq!("read 0x301 = 0x00 at 0x206", hit.to_string());
+    }
+
+    #[test]
+    fn save_state_test() {
+        let mut chip8 = Chip8::new();
+        // LD V0, 0x01 - ADD V0, 0x01 - JP 0x202
+        chip8.load_rom(&[0x60, 0x01, 0x70, 0x01, 0x12, 0x02]).unwrap();
+        chip8.run_cycles(2).unwrap();
+        let state = chip8.save_state();
+
+        chip8.run_cycles(10).unwrap();
+        chip8.load_state(&state).unwrap();
+        assert_eq!(0x02, chip8.cpu().get_reg(0));
+        assert_eq!(0x204, chip8.cpu().pc);
+
+        let mut other = Chip8::new();
+        other.load_rom(&[0x00, 0xE0]).unwrap();
+        assert_eq!(Err(StateError::RomMismatch), other.load_state(&state));
+    }
+
+    #[test]
+    fn seed_test() {
+        // RND V0, 0xFF - JP 0x200
+        let rom = [0xC0, 0xFF, 0x12, 0x00];
+        let run = |seed| {
+            let mut chip8 = Chip8::new();
+            chip8.load_rom(&rom).unwrap();
+            chip8.set_seed(seed);
+            (0..8).map(|_| {
+                chip8.run_cycles(2).unwrap();
+                chip8.cpu().get_reg(0)
+            }).collect::<Vec<_>>()
+        };
+
+        assert_eq!(run(1234), run(1234));
+        assert_ne!(run(1234), run(4321));
+    }
+}
@@ -0,0 +1,539 @@
+use std::str::FromStr;
+
+use chip8r::capture::{self, ImageFormat, FORMAT_NAMES};
+use chip8r::graphic::{self, RenderMode};
+use chip8r::palette::{self, Palette, THEME_NAMES};
+use crossterm::style::Color;
+use chip8r::keyboard;
+use chip8r::keymap::{Keymap, PRESET_NAMES};
+use chip8r::quirks::{Quirks, PROFILE_NAMES};
+
+// Longest --rewind, an hour of play
+const MAX_REWIND: u32 = 3600;
+
+pub const USAGE: &str = "chip8r - a basic chip8 emulator
+
+Usage:
+    chip8r run <rom> [options]    Run a rom, use - to read it from stdin
+    chip8r disasm <rom> [--source]
+                                  Disassemble a rom, --source prints assembly
+                                  that chip8r asm turns back into the rom
+    chip8r asm <source> [-o <rom>] [--symbols <file>]
+                                  Assemble a rom, written next to the source
+                                  with a .ch8 extension unless -o is given
+    chip8r replay <movie> <rom> [--headless] [--hz <n>] [--scale <n>]
+                  [--renderer <mode>] [--screenshot <file>]
+                                  Play back a movie recorded with --movie,
+                                  --headless runs it without a terminal and
+                                  prints the final screen, --screenshot
+                                  saves it as a .png, .ppm or .pbm image
+    chip8r config show [rom] [--config <file>]
+                                  Print the settings for the given rom and
+                                  where each came from: config file, rom
+                                  table or .quirks/.keymap next to the rom
+    chip8r list [dir]             List the roms in dir (default: roms/)
+    chip8r help                   Show this message
+
+Run options, overriding the config file:
+    --config <file>   Config file (default: ~/.config/chip8r/config.toml)
+    --ipf <n>         Instructions executed per frame (default: 10)
+    --cpu-hz <n>      Instructions executed per second, instead of --ipf
+    --hz <n>          Frames rendered per second (default: 60)
+    --scale <n>       Terminal cells per pixel (default: 1, at most 511)
+    --renderer <mode> Pixels drawn with block (2x1 cells per pixel), half
+                      (half blocks, 1x1/2) or braille (1/2x1/4), falls
+                      back to a lower scale then a denser mode when the
+                      terminal is too small (default: block)
+    --theme <name>    Colours: default, green, amber, lcd, high-contrast,
+                      24-bit when COLORTERM says so, else 256 colours
+    --foreground <c>  Colour of the pixels that are on, #rrggbb or a name
+    --background <c>  Colour of the pixels that are off, default is the
+                      terminal background
+    --quirks <name>   Quirk profile: vip, chip48, schip, xochip (default: vip,
+                      or the profile named in <rom>.quirks next to the rom)
+    --keymap <k>      Keypad keys: qwerty, azerty, hex, or a file with the
+                      16 keys laid out as the keypad (default: qwerty, or
+                      the keymap in <rom>.keymap next to the rom)
+    --seed <n>        Seed for the RND instruction, random by default
+    --paused          Start paused, press p to resume
+    --cache           Cache decoded instructions, for high --ipf/--cpu-hz,
+                      a few percent up to 20% faster depending on the rom
+    --debug           Start paused in the debugger, with a side panel
+    --rewind <secs>   Seconds of play kept for rewinding, 0 to disable
+                      (default: 10, at most 3600)
+    --load-state <f>  Start from a save state, F5/F9 then use this file
+                      instead of <rom>.state
+    --key-timeout <ms>
+                      Release a key when the terminal has not repeated it
+                      for this long, for terminals that do not report key
+                      releases (default: 150, at least 600 until the
+                      first repeat)
+    --movie <f>       Record the keys pressed to a movie file, with the
+                      seed and quirks, to reproduce the run with replay
+    --record <f>      Record the screen to an animated GIF from the start,
+                      F3 stops the recording
+    --capture-scale <n>
+                      Image pixels per pixel in screenshots and recordings
+                      (default: 4, at most 32)
+    --capture-theme <name>
+                      Colours of screenshots and recordings (default: the
+                      colours of the display)
+    --screenshot-format <f>
+                      Format of the F2 screenshots: png, ppm, pbm
+                      (default: png)
+
+Keys:
+    1234 / qwer /     CHIP-8 keypad 123C / 456D / 789E / A0BF,
+    asdf / zxcv       with the qwerty keymap
+    p                 Pause / resume
+    F2                Screenshot, saved next to the rom as <rom>-<n>.png
+    F3                Start / stop recording to <rom>-<n>.gif
+    F5 / F9           Save / load the state
+    Backspace (hold)  Rewind
+    Esc               Quit
+
+Debugger keys (--debug):
+    F11               Step one instruction
+    F10               Step over a CALL
+    g / p             Continue / pause
+    :                 Command prompt: step, next, continue, until <addr>,
+                      break <addr>, delete <addr> (addresses in hex),
+                      watch <Vx|I>, watch <addr|start-end> [r|w|rw],
+                      unwatch <Vx|I|addr|start-end>";
+
+#[derive(Debug, PartialEq)]
+pub enum Command {
+    Run(RunOptions),
+    Disasm { rom: RomSource, source: bool },
+    Asm { source: String, output: Option<String>, symbols: Option<String> },
+    ShowConfig { rom: Option<RomSource>, config: Option<String> },
+    Replay { movie: String, rom: RomSource, headless: bool, hz: u32, scale: u16, renderer: RenderMode, screenshot: Option<String> },
+    List(Option<String>),
+    Help
+}
+
+#[derive(Debug, PartialEq)]
+pub enum RomSource {
+    File(String),
+    Stdin
+}
+
+#[derive(Debug, PartialEq)]
+pub struct RunOptions {
+    pub rom: RomSource,
+    pub config: Option<String>,
+    pub ipf: Option<u32>,
+    pub cpu_hz: Option<u32>,
+    pub hz: Option<u32>,
+    pub scale: Option<u16>,
+    pub renderer: Option<RenderMode>,
+    pub theme: Option<Palette>,
+    pub foreground: Option<Color>,
+    pub background: Option<Color>,
+    pub quirks: Option<Quirks>,
+    pub keymap: Option<String>,
+    pub seed: Option<u64>,
+    pub paused: bool,
+    pub cache: bool,
+    pub debug: bool,
+    pub load_state: Option<String>,
+    pub rewind: u32,
+    pub key_timeout: u64,
+    pub movie: Option<String>,
+    pub record: Option<String>,
+    pub capture_scale: u16,
+    pub capture_theme: Option<Palette>,
+    pub screenshot_format: ImageFormat
+}
+
+impl RunOptions {
+    fn new(rom: RomSource) -> Self {
+        RunOptions {
+            rom,
+            config: None,
+            ipf: None,
+            cpu_hz: None,
+            hz: None,
+            scale: None,
+            renderer: None,
+            theme: None,
+            foreground: None,
+            background: None,
+            quirks: None,
+            keymap: None,
+            seed: None,
+            paused: false,
+            cache: false,
+            debug: false,
+            load_state: None,
+            rewind: 10,
+            key_timeout: keyboard::DEFAULT_AUTO_RELEASE.as_millis() as u64,
+            movie: None,
+            record: None,
+            capture_scale: capture::DEFAULT_SCALE,
+            capture_theme: None,
+            screenshot_format: ImageFormat::Png
+        }
+    }
+}
+
+/// Parses the command line arguments, without the program name.
+pub fn parse_args(args: &[String]) -> Result<Command, String> {
+    let mut args = args.iter();
+    match args.next().map(String::as_str) {
+        None | Some("help") | Some("-h") | Some("--help") => Ok(Command::Help),
+        Some("list") => {
+            let dir = args.next().cloned();
+            if let Some(arg) = args.next() {
+                return Err(format!("unexpected argument '{}'", arg));
+            }
+            Ok(Command::List(dir))
+        }
+        Some("run") => parse_run_args(args).map(Command::Run),
+        Some("disasm") => {
+            let mut rom = None;
+            let mut source = false;
+            for arg in args {
+                match arg.as_str() {
+                    "--source" => source = true,
+                    flag if flag.starts_with("--") => return Err(format!("unknown option '{}'", flag)),
+                    path if rom.is_none() => rom = Some(rom_source(path)),
+                    path => return Err(format!("unexpected argument '{}'", path))
+                }
+            }
+            let rom = rom.ok_or_else(|| "missing rom, use - to read it from stdin".to_string())?;
+            Ok(Command::Disasm { rom, source })
+        }
+        Some("asm") => {
+            let mut source = None;
+            let mut output = None;
+            let mut symbols = None;
+            while let Some(arg) = args.next() {
+                match arg.as_str() {
+                    "-o" | "--output" => output = Some(parse_value(arg, args.next())?),
+                    "--symbols" => symbols = Some(parse_value(arg, args.next())?),
+                    flag if flag.starts_with('-') => return Err(format!("unknown option '{}'", flag)),
+                    path if source.is_none() => source = Some(path.to_string()),
+                    path => return Err(format!("unexpected argument '{}'", path))
+                }
+            }
+            let source = source.ok_or_else(|| "missing source file".to_string())?;
+            Ok(Command::Asm { source, output, symbols })
+        }
+        Some("config") => {
+            if args.next().map(String::as_str) != Some("show") {
+                return Err("expected config show".to_string());
+            }
+            let mut rom = None;
+            let mut config = None;
+            while let Some(arg) = args.next() {
+                match arg.as_str() {
+                    "--config" => config = Some(parse_value(arg, args.next())?),
+                    flag if flag.starts_with("--") => return Err(format!("unknown option '{}'", flag)),
+                    path if rom.is_none() => rom = Some(rom_source(path)),
+                    path => return Err(format!("unexpected argument '{}'", path))
+                }
+            }
+            Ok(Command::ShowConfig { rom, config })
+        }
+        Some("replay") => {
+            let mut paths = Vec::new();
+            let mut headless = false;
+            let mut hz = 60;
+            let mut scale = 1;
+            let mut renderer = RenderMode::Block;
+            let mut screenshot = None;
+            while let Some(arg) = args.next() {
+                match arg.as_str() {
+                    "--headless" => headless = true,
+                    "--hz" => hz = parse_value(arg, args.next())?,
+                    "--scale" => scale = parse_value(arg, args.next())?,
+                    "--renderer" => renderer = parse_renderer(&parse_value::<String>(arg, args.next())?)?,
+                    "--screenshot" => {
+                        let path: String = parse_value(arg, args.next())?;
+                        if ImageFormat::from_path(path.as_ref()).is_none() {
+                            return Err(format!("--screenshot {}: expected a {} file", path, FORMAT_NAMES.join(", ")));
+                        }
+                        screenshot = Some(path);
+                    }
+                    flag if flag.starts_with("--") => return Err(format!("unknown option '{}'", flag)),
+                    path if paths.len() < 2 => paths.push(path.to_string()),
+                    path => return Err(format!("unexpected argument '{}'", path))
+                }
+            }
+            if hz == 0 || scale == 0 {
+                return Err("--hz and --scale must be greater than 0".to_string());
+            }
+            if scale > graphic::MAX_SCALE {
+                return Err(format!("--scale must be at most {}", graphic::MAX_SCALE));
+            }
+            match paths.as_slice() {
+                [movie, rom] => Ok(Command::Replay { movie: movie.clone(), rom: rom_source(rom), headless, hz, scale, renderer, screenshot }),
+                _ => Err("expected a movie and a rom".to_string())
+            }
+        }
+        Some(command) => Err(format!("unknown command '{}'", command))
+    }
+}
+
+fn parse_run_args<'a>(mut args: impl Iterator<Item = &'a String>) -> Result<RunOptions, String> {
+    let mut rom = None;
+    let mut options = RunOptions::new(RomSource::Stdin);
+
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--config" => options.config = Some(parse_value(arg, args.next())?),
+            "--ipf" => options.ipf = Some(parse_value(arg, args.next())?),
+            "--cpu-hz" => options.cpu_hz = Some(parse_value(arg, args.next())?),
+            "--hz" => options.hz = Some(parse_value(arg, args.next())?),
+            "--scale" => options.scale = Some(parse_value(arg, args.next())?),
+            "--renderer" => options.renderer = Some(parse_renderer(&parse_value::<String>(arg, args.next())?)?),
+            "--theme" => options.theme = Some(parse_theme(&parse_value::<String>(arg, args.next())?)?),
+            "--foreground" => options.foreground = Some(parse_color(arg, args.next())?),
+            "--background" => options.background = Some(parse_color(arg, args.next())?),
+            "--record" => options.record = Some(parse_value(arg, args.next())?),
+            "--capture-scale" => options.capture_scale = parse_value(arg, args.next())?,
+            "--capture-theme" => options.capture_theme = Some(parse_theme(&parse_value::<String>(arg, args.next())?)?),
+            "--screenshot-format" => {
+                let name: String = parse_value(arg, args.next())?;
+                options.screenshot_format = ImageFormat::from_name(&name)
+                    .ok_or_else(|| format!("unknown image format '{}', expected one of {}", name.trim(), FORMAT_NAMES.join(", ")))?;
+            }
+            "--seed" => options.seed = Some(parse_value(arg, args.next())?),
+            "--quirks" => {
+                let name: String = parse_value(arg, args.next())?;
+                options.quirks = Some(parse_quirks(&name)?);
+            }
+            "--keymap" => options.keymap = Some(parse_value(arg, args.next())?),
+            "--paused" => options.paused = true,
+            "--cache" => options.cache = true,
+            "--debug" => options.debug = true,
+            "--rewind" => options.rewind = parse_value(arg, args.next())?,
+            "--load-state" => options.load_state = Some(parse_value(arg, args.next())?),
+            "--key-timeout" => options.key_timeout = parse_value(arg, args.next())?,
+            "--movie" => options.movie = Some(parse_value(arg, args.next())?),
+            flag if flag.starts_with("--") => return Err(format!("unknown option '{}'", flag)),
+            path => {
+                if rom.is_some() {
+                    return Err(format!("unexpected argument '{}'", path));
+                }
+                rom = Some(rom_source(path));
+            }
+        }
+    }
+
+    if options.ipf == Some(0) || options.cpu_hz == Some(0) || options.hz == Some(0) || options.scale == Some(0) || options.capture_scale == 0 {
+        return Err("--ipf, --cpu-hz, --hz, --scale and --capture-scale must be greater than 0".to_string());
+    }
+    if options.scale.is_some_and(|scale| scale > graphic::MAX_SCALE) {
+        return Err(format!("--scale must be at most {}", graphic::MAX_SCALE));
+    }
+    if options.capture_scale > capture::MAX_SCALE {
+        return Err(format!("--capture-scale must be at most {}", capture::MAX_SCALE));
+    }
+    if options.rewind > MAX_REWIND {
+        return Err(format!("--rewind must be at most {} seconds", MAX_REWIND));
+    }
+    // A movie replays from power on, without anything the input does not capture
+    if options.movie.is_some() && (options.debug || options.load_state.is_some()) {
+        return Err("--movie cannot be combined with --debug or --load-state".to_string());
+    }
+
+    options.rom = rom.ok_or_else(|| "missing rom, use - to read it from stdin".to_string())?;
+    Ok(options)
+}
+
+fn rom_source(path: &str) -> RomSource {
+    match path {
+        "-" => RomSource::Stdin,
+        path => RomSource::File(path.to_string())
+    }
+}
+
+pub fn parse_quirks(name: &str) -> Result<Quirks, String> {
+    Quirks::from_name(name)
+        .ok_or_else(|| format!("unknown quirk profile '{}', expected one of {}", name.trim(), PROFILE_NAMES.join(", ")))
+}
+
+pub fn parse_renderer(name: &str) -> Result<RenderMode, String> {
+    RenderMode::from_name(name)
+        .ok_or_else(|| format!("unknown renderer '{}', expected one of {}", name.trim(), RenderMode::NAMES.join(", ")))
+}
+
+pub fn parse_theme(name: &str) -> Result<Palette, String> {
+    Palette::from_name(name)
+        .ok_or_else(|| format!("unknown theme '{}', expected one of {}", name.trim(), THEME_NAMES.join(", ")))
+}
+
+/// A keymap preset, or a file holding a keymap.
+pub fn parse_keymap(value: &str) -> Result<Keymap, String> {
+    if let Some(keymap) = Keymap::from_name(value) {
+        return Ok(keymap);
+    }
+    let text = std::fs::read_to_string(value)
+        .map_err(|msg| format!("'{}' is neither a keymap ({}) nor a readable file - {}", value, PRESET_NAMES.join(", "), msg))?;
+    Keymap::parse(&text).map_err(|msg| format!("{} - {}", value, msg))
+}
+
+fn parse_color(flag: &str, value: Option<&String>) -> Result<Color, String> {
+    let value: String = parse_value(flag, value)?;
+    palette::parse_color(&value).ok_or_else(|| format!("invalid colour '{}' for {}, expected #rrggbb or a colour name such as white or dark_green", value, flag))
+}
+
+fn parse_value<T: FromStr>(flag: &str, value: Option<&String>) -> Result<T, String> {
+    let value = value.ok_or_else(|| format!("missing value for {}", flag))?;
+    value.parse().map_err(|_| format!("invalid value '{}' for {}", value, flag))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(args: &str) -> Result<Command, String> {
+        let args: Vec<String> = args.split_whitespace().map(String::from).collect();
+        parse_args(&args)
+    }
+
+    #[test]
+    fn parse_run_test() {
+        let command = parse("run roms/pong.rom --config chip8r.toml --ipf 12 --hz 30 --scale 2 --renderer half --theme amber --foreground #ffffff --background black --quirks schip --keymap azerty --seed 42 --paused --debug --load-state pong.state --rewind 0 --key-timeout 300").unwrap();
+
+        assert_eq!(Command::Run(RunOptions {
+            rom: RomSource::File("roms/pong.rom".to_string()),
+            config: Some("chip8r.toml".to_string()),
+            ipf: Some(12),
+            cpu_hz: None,
+            hz: Some(30),
+            scale: Some(2),
+            renderer: Some(RenderMode::HalfBlock),
+            theme: Some(Palette::AMBER),
+            foreground: Some(Color::Rgb { r: 0xFF, g: 0xFF, b: 0xFF }),
+            background: Some(Color::Black),
+            quirks: Some(Quirks::SCHIP),
+            keymap: Some("azerty".to_string()),
+            seed: Some(42),
+            paused: true,
+            cache: false,
+            debug: true,
+            load_state: Some("pong.state".to_string()),
+            rewind: 0,
+            key_timeout: 300,
+            movie: None,
+            record: None,
+            capture_scale: 4,
+            capture_theme: None,
+            screenshot_format: ImageFormat::Png
+        }), command);
+    }
+
+    #[test]
+    fn parse_capture_test() {
+        match parse("run pong.rom --record pong.gif --capture-scale 2 --capture-theme lcd --screenshot-format pbm").unwrap() {
+            Command::Run(options) => {
+                assert_eq!(Some("pong.gif".to_string()), options.record);
+                assert_eq!((2, Some(Palette::LCD), ImageFormat::Pbm), (options.capture_scale, options.capture_theme, options.screenshot_format));
+            }
+            command => panic!("unexpected command {:?}", command)
+        }
+        match parse("replay pong.movie pong.rom --headless --screenshot end.ppm").unwrap() {
+            Command::Replay { screenshot, .. } => assert_eq!(Some("end.ppm".to_string()), screenshot),
+            command => panic!("unexpected command {:?}", command)
+        }
+
+        assert!(parse("run pong.rom --capture-scale 0").is_err());
+        assert_eq!(Err("--capture-scale must be at most 32".to_string()), parse("run pong.rom --capture-scale 33"));
+        assert!(parse("run pong.rom --screenshot-format jpg").is_err());
+        assert!(parse("replay pong.movie pong.rom --screenshot end.jpg").is_err());
+    }
+
+    #[test]
+    fn parse_movie_test() {
+        match parse("run pong.rom --movie pong.movie").unwrap() {
+            Command::Run(options) => assert_eq!(Some("pong.movie".to_string()), options.movie),
+            command => panic!("unexpected command {:?}", command)
+        }
+        assert!(parse("run pong.rom --movie pong.movie --debug").is_err());
+
+        assert_eq!(Command::Replay {
+            movie: "pong.movie".to_string(),
+            rom: RomSource::File("pong.rom".to_string()),
+            headless: true,
+            hz: 30,
+            scale: 1,
+            renderer: RenderMode::Braille,
+            screenshot: None
+        }, parse("replay pong.movie pong.rom --headless --hz 30 --renderer braille").unwrap());
+        assert!(parse("replay pong.movie").is_err());
+    }
+
+    #[test]
+    fn parse_run_cpu_hz_test() {
+        match parse("run pong.rom --cpu-hz 500").unwrap() {
+            Command::Run(options) => assert_eq!(Some(500), options.cpu_hz),
+            command => panic!("unexpected command {:?}", command)
+        }
+    }
+
+    #[test]
+    fn parse_run_stdin_test() {
+        match parse("run -").unwrap() {
+            Command::Run(options) => assert_eq!(RomSource::Stdin, options.rom),
+            command => panic!("unexpected command {:?}", command)
+        }
+    }
+
+    #[test]
+    fn parse_disasm_test() {
+        assert_eq!(Command::Disasm { rom: RomSource::File("pong.rom".to_string()), source: true }, parse("disasm pong.rom --source").unwrap());
+        assert_eq!(Command::Disasm { rom: RomSource::Stdin, source: false }, parse("disasm -").unwrap());
+        assert!(parse("disasm").is_err());
+    }
+
+    #[test]
+    fn parse_asm_test() {
+        assert_eq!(Command::Asm { source: "pong.asm".to_string(), output: None, symbols: None }, parse("asm pong.asm").unwrap());
+        assert_eq!(Command::Asm {
+            source: "pong.asm".to_string(),
+            output: Some("out.ch8".to_string()),
+            symbols: Some("out.sym".to_string())
+        }, parse("asm pong.asm -o out.ch8 --symbols out.sym").unwrap());
+        assert!(parse("asm").is_err());
+        assert!(parse("asm pong.asm -o").is_err());
+    }
+
+    #[test]
+    fn parse_config_test() {
+        assert_eq!(Command::ShowConfig { rom: None, config: None }, parse("config show").unwrap());
+        assert_eq!(Command::ShowConfig {
+            rom: Some(RomSource::File("pong.rom".to_string())),
+            config: Some("chip8r.toml".to_string())
+        }, parse("config show pong.rom --config chip8r.toml").unwrap());
+        assert!(parse("config").is_err());
+        assert!(parse("config show a b").is_err());
+    }
+
+    #[test]
+    fn parse_errors_test() {
+        assert!(parse("run").is_err());
+        assert!(parse("run pong.rom --ipf").is_err());
+        assert!(parse("run pong.rom --ipf fast").is_err());
+        assert!(parse("run pong.rom --scale 0").is_err());
+        assert_eq!(Err("--scale must be at most 511".to_string()), parse("run pong.rom --scale 520"));
+        assert_eq!(Err("--scale must be at most 511".to_string()), parse("replay pong.movie pong.rom --scale 520"));
+        assert_eq!(Err("--rewind must be at most 3600 seconds".to_string()), parse("run pong.rom --rewind 3601"));
+        assert!(parse("run pong.rom --renderer ascii").is_err());
+        assert!(parse("run pong.rom --theme sepia").is_err());
+        assert!(parse("run pong.rom --foreground #fff").is_err());
+        assert!(parse("run pong.rom --quirks nes").is_err());
+        assert!(parse("run pong.rom --frobnicate").is_err());
+        assert!(parse("explode").is_err());
+    }
+
+    #[test]
+    fn parse_help_and_list_test() {
+        assert_eq!(Command::Help, parse("").unwrap());
+        assert_eq!(Command::Help, parse("--help").unwrap());
+        assert_eq!(Command::List(None), parse("list").unwrap());
+        assert_eq!(Command::List(Some("games".to_string())), parse("list games").unwrap());
+    }
+}
@@ -0,0 +1,551 @@
+use std::collections::BTreeMap;
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use crossterm::style::Color;
+use toml::Value;
+
+use crate::graphic::{self, RenderMode};
+use crate::keymap::Keymap;
+use crate::palette::{color_name, parse_color, Palette, THEME_NAMES};
+use crate::quirks::{Quirks, FIELD_NAMES, PROFILE_NAMES};
+use crate::savestate::RomHash;
+
+/// Settings of a run, once the defaults, the config file and the command
+/// line have been applied.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Settings {
+    // Instructions per frame, unless cpu_hz is set
+    pub ipf: u32,
+    pub cpu_hz: Option<u32>,
+    pub hz: u32,
+    pub scale: u16,
+    pub quirks: Quirks,
+    pub keymap: Keymap,
+    pub palette: Palette,
+    pub renderer: RenderMode
+}
+
+impl Default for Settings {
+    fn default() -> Self {
+        Settings {
+            ipf: 10,
+            cpu_hz: None,
+            hz: 60,
+            scale: 1,
+            quirks: Quirks::default(),
+            keymap: Keymap::default(),
+            palette: Palette::default(),
+            renderer: RenderMode::Block
+        }
+    }
+}
+
+impl Settings {
+    /// The settings as a config file, each value followed by a comment
+    /// naming where it came from.
+    pub fn show(&self, sources: &Sources) -> String {
+        let mut text = String::new();
+        // Writing to a string cannot fail
+        self.write(&mut text, Some(sources)).unwrap();
+        text
+    }
+
+    fn write(&self, f: &mut impl fmt::Write, sources: Option<&Sources>) -> fmt::Result {
+        let from = |key: &str| sources.map(|sources| format!("  # {}", sources.get(key))).unwrap_or_default();
+        writeln!(f, "ipf = {}{}", self.ipf, from("ipf"))?;
+        if let Some(cpu_hz) = self.cpu_hz {
+            writeln!(f, "cpu-hz = {}{}", cpu_hz, from("cpu-hz"))?;
+        }
+        writeln!(f, "hz = {}{}", self.hz, from("hz"))?;
+        writeln!(f, "scale = {}{}", self.scale, from("scale"))?;
+        writeln!(f, "renderer = \"{}\"{}", self.renderer.name(), from("renderer"))?;
+        match self.palette.name() {
+            Some(name) => writeln!(f, "theme = \"{}\"{}", name, from("palette"))?,
+            None => {
+                let colors: Vec<String> = self.palette.colors.iter().map(|color| format!("\"{}\"", color_name(*color))).collect();
+                writeln!(f, "palette = [{}]{}", colors.join(", "), from("palette"))?
+            }
+        }
+        match self.keymap.name() {
+            Some(name) => writeln!(f, "keymap = \"{}\"{}", name, from("keymap"))?,
+            None => writeln!(f, "keymap = \"\"\"\n{}\"\"\"{}", self.keymap.to_string().replace('\\', "\\\\").replace('"', "\\\""), from("keymap"))?
+        }
+        writeln!(f)?;
+        writeln!(f, "[quirks]")?;
+        for (name, value) in self.quirks.fields() {
+            match value.parse::<bool>() {
+                Ok(flag) => writeln!(f, "{} = {}{}", name, flag, from(name))?,
+                Err(_) => writeln!(f, "{} = \"{}\"{}", name, value, from(name))?
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Writes the settings as a config file `Config::parse` reads back.
+impl fmt::Display for Settings {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        self.write(f, None)
+    }
+}
+
+/// Where a setting came from, lowest precedence first. The command line
+/// goes over all of them.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Source {
+    Default,
+    // The global settings of the config file
+    Config,
+    // `<rom>.quirks` and `<rom>.keymap`
+    RomFile,
+    // The `[rom.<sha1>]` table of the config file
+    RomTable
+}
+
+impl fmt::Display for Source {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match self {
+            Source::Default => "default",
+            Source::Config => "config",
+            Source::RomFile => "file next to the rom",
+            Source::RomTable => "[rom.<sha1>] table"
+        })
+    }
+}
+
+/// The source of every setting, by its name in the config file. The
+/// colours are all under `palette`.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Sources(BTreeMap<String, Source>);
+
+impl Sources {
+    pub fn get(&self, key: &str) -> Source {
+        self.0.get(key).copied().unwrap_or(Source::Default)
+    }
+
+    fn set(&mut self, key: &str, source: Source) {
+        self.0.insert(key.to_string(), source);
+    }
+}
+
+/// Settings from the files next to a rom: `<rom>.quirks` holds a quirk
+/// profile name and `<rom>.keymap` a keymap.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct RomFiles {
+    pub quirks: Option<Quirks>,
+    pub keymap: Option<Keymap>
+}
+
+impl RomFiles {
+    /// Reads the files next to `rom`, the missing ones set nothing.
+    pub fn load(rom: &Path) -> Result<RomFiles, String> {
+        let read = |extension: &str| {
+            let path = rom.with_extension(extension);
+            if !path.is_file() {
+                return Ok(None);
+            }
+            match fs::read_to_string(&path) {
+                Ok(text) => Ok(Some((path, text))),
+                Err(msg) => Err(format!("could not read {} - {}", path.display(), msg))
+            }
+        };
+
+        let quirks = match read("quirks")? {
+            Some((path, name)) => Some(Quirks::from_name(&name).ok_or_else(|| {
+                format!("{} - unknown quirk profile '{}', expected one of {}", path.display(), name.trim(), PROFILE_NAMES.join(", "))
+            })?),
+            None => None
+        };
+        let keymap = match read("keymap")? {
+            Some((path, text)) => Some(Keymap::parse(&text).map_err(|msg| format!("{} - {}", path.display(), msg))?),
+            None => None
+        };
+        Ok(RomFiles { quirks, keymap })
+    }
+}
+
+/// The config file: global settings, then `[rom.<sha1>]` tables with the
+/// settings of a single rom, keyed by the SHA-1 of the rom in hex.
+///
+/// ```toml
+/// ipf = 12
+/// keymap = "azerty"
+/// theme = "amber"
+/// foreground = "#33ff66"
+///
+/// [quirks]
+/// profile = "schip"
+/// key_on_press = false
+///
+/// [rom.b232ef880bd6060fb45fa6effed7edf0ae95670e]
+/// cpu-hz = 500
+/// quirks = "vip"
+/// ```
+///
+/// Every setting is optional, missing ones keep their default. A rom table
+/// goes over the `.quirks` and `.keymap` files next to the rom, which go
+/// over the global settings.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Config {
+    global: Table,
+    roms: BTreeMap<String, Table>
+}
+
+// Settings found in the global table or in a rom table, checked when read
+#[derive(Debug, Clone, Default, PartialEq)]
+struct Table {
+    ipf: Option<u32>,
+    cpu_hz: Option<u32>,
+    hz: Option<u32>,
+    scale: Option<u16>,
+    quirk_profile: Option<Quirks>,
+    // Applied on top of the profile, in order
+    quirk_fields: Vec<(String, String)>,
+    keymap: Option<Keymap>,
+    theme: Option<Palette>,
+    // Applied over the theme, the off and on colours then the two others
+    palette: Vec<Color>,
+    foreground: Option<Color>,
+    background: Option<Color>,
+    renderer: Option<RenderMode>
+}
+
+impl Config {
+    /// `$XDG_CONFIG_HOME/chip8r/config.toml`, or `~/.config/chip8r/config.toml`.
+    pub fn default_path() -> Option<PathBuf> {
+        let base = match std::env::var_os("XDG_CONFIG_HOME").filter(|dir| !dir.is_empty()) {
+            Some(dir) => PathBuf::from(dir),
+            None => PathBuf::from(std::env::var_os("HOME")?).join(".config")
+        };
+        Some(base.join("chip8r").join("config.toml"))
+    }
+
+    /// Reads a config file, a missing file is an empty config.
+    pub fn load(path: &Path) -> Result<Config, String> {
+        match fs::read_to_string(path) {
+            Ok(text) => Config::parse(&text).map_err(|msg| format!("{} - {}", path.display(), msg)),
+            Err(error) if error.kind() == io::ErrorKind::NotFound => Ok(Config::default()),
+            Err(msg) => Err(format!("could not read {} - {}", path.display(), msg))
+        }
+    }
+
+    pub fn parse(text: &str) -> Result<Config, String> {
+        let value: Value = text.parse().map_err(|error: toml::de::Error| error.to_string())?;
+        let mut table = match value {
+            Value::Table(table) => table,
+            _ => return Err("expected a table".to_string())
+        };
+
+        let mut roms = BTreeMap::new();
+        if let Some(rom_tables) = table.remove("rom") {
+            let rom_tables = match rom_tables {
+                Value::Table(rom_tables) => rom_tables,
+                _ => return Err("rom: expected tables keyed by the SHA-1 of the roms".to_string())
+            };
+            for (hash, rom_table) in rom_tables {
+                let hash = hash.to_lowercase();
+                if hash.len() != 40 || !hash.chars().all(|c| c.is_ascii_hexdigit()) {
+                    return Err(format!("rom.{}: expected the SHA-1 of the rom in hex", hash));
+                }
+                match rom_table {
+                    Value::Table(rom_table) => {
+                        let parsed = Table::parse(rom_table).map_err(|msg| format!("rom.{}.{}", hash, msg))?;
+                        roms.insert(hash, parsed);
+                    }
+                    _ => return Err(format!("rom.{}: expected a table", hash))
+                }
+            }
+        }
+
+        Ok(Config { global: Table::parse(table)?, roms })
+    }
+
+    /// The settings for the rom with the given hash, the rom table applied
+    /// over the global one.
+    pub fn settings(&self, rom_hash: Option<&RomHash>) -> Settings {
+        self.rom_settings(rom_hash, &RomFiles::default()).0
+    }
+
+    /// The settings for a rom and where each came from: the global table,
+    /// then the files next to the rom, then the rom table.
+    pub fn rom_settings(&self, rom_hash: Option<&RomHash>, files: &RomFiles) -> (Settings, Sources) {
+        let mut settings = Settings::default();
+        let mut sources = Sources::default();
+        self.global.apply(&mut settings, &mut sources, Source::Config);
+
+        if let Some(quirks) = files.quirks {
+            settings.quirks = quirks;
+            for name in FIELD_NAMES {
+                sources.set(name, Source::RomFile);
+            }
+        }
+        if let Some(keymap) = files.keymap {
+            settings.keymap = keymap;
+            sources.set("keymap", Source::RomFile);
+        }
+
+        let rom_table = rom_hash.and_then(|hash| self.roms.get(&hex(hash)));
+        if let Some(rom_table) = rom_table {
+            rom_table.apply(&mut settings, &mut sources, Source::RomTable);
+        }
+        (settings, sources)
+    }
+}
+
+impl Table {
+    fn parse(table: toml::value::Table) -> Result<Table, String> {
+        let mut parsed = Table::default();
+        for (key, value) in table {
+            let error = |msg: &str| format!("{}: {}", key, msg);
+            match key.as_str() {
+                "ipf" => parsed.ipf = Some(positive(&value).ok_or_else(|| error("expected a number greater than 0"))?),
+                "cpu-hz" => parsed.cpu_hz = Some(positive(&value).ok_or_else(|| error("expected a number greater than 0"))?),
+                "hz" => parsed.hz = Some(positive(&value).ok_or_else(|| error("expected a number greater than 0"))?),
+                "scale" => {
+                    parsed.scale = Some(positive(&value).filter(|scale| *scale <= graphic::MAX_SCALE)
+                        .ok_or_else(|| error(&format!("expected a number from 1 to {}", graphic::MAX_SCALE)))?);
+                }
+                "keymap" => {
+                    let text = value.as_str().ok_or_else(|| error("expected a keymap name or layout"))?;
+                    parsed.keymap = Some(Keymap::parse(text).map_err(|msg| error(&msg))?);
+                }
+                "theme" => {
+                    parsed.theme = Some(value.as_str().and_then(Palette::from_name)
+                        .ok_or_else(|| error(&format!("expected one of {}", THEME_NAMES.join(", "))))?);
+                }
+                "palette" => {
+                    let colors = value.as_array().filter(|colors| (1..=4).contains(&colors.len()))
+                        .ok_or_else(|| error("expected a list of 1 to 4 colours, off, on, then the second XO-CHIP plane and both planes"))?;
+                    parsed.palette = colors.iter().map(|color| color.as_str().and_then(parse_color).ok_or_else(|| error(COLOR_ERROR)))
+                        .collect::<Result<_, _>>()?;
+                }
+                "foreground" | "background" => {
+                    let color = value.as_str().and_then(parse_color).ok_or_else(|| error(COLOR_ERROR))?;
+                    if key == "foreground" {
+                        parsed.foreground = Some(color);
+                    } else {
+                        parsed.background = Some(color);
+                    }
+                }
+                "renderer" => {
+                    parsed.renderer = Some(value.as_str().and_then(RenderMode::from_name)
+                        .ok_or_else(|| error(&format!("expected one of {}", RenderMode::NAMES.join(", "))))?);
+                }
+                "quirks" => parse_quirks(&mut parsed, &value).map_err(|msg| error(&msg))?,
+                _ => return Err(error("unknown setting"))
+            }
+        }
+        Ok(parsed)
+    }
+
+    /// Applies the settings of the table, noting `source` for each.
+    fn apply(&self, settings: &mut Settings, sources: &mut Sources, source: Source) {
+        if let Some(ipf) = self.ipf {
+            settings.ipf = ipf;
+            settings.cpu_hz = None;
+            sources.set("ipf", source);
+        }
+        if self.cpu_hz.is_some() {
+            settings.cpu_hz = self.cpu_hz;
+            sources.set("cpu-hz", source);
+        }
+        if let Some(hz) = self.hz {
+            settings.hz = hz;
+            sources.set("hz", source);
+        }
+        if let Some(scale) = self.scale {
+            settings.scale = scale;
+            sources.set("scale", source);
+        }
+        if let Some(quirks) = self.quirk_profile {
+            settings.quirks = quirks;
+            for name in FIELD_NAMES {
+                sources.set(name, source);
+            }
+        }
+        for (name, value) in &self.quirk_fields {
+            // Checked when parsed
+            settings.quirks.set(name, value);
+            sources.set(name, source);
+        }
+        if let Some(keymap) = self.keymap {
+            settings.keymap = keymap;
+            sources.set("keymap", source);
+        }
+        settings.palette = self.theme.unwrap_or(settings.palette);
+        for (idx, color) in self.palette.iter().enumerate() {
+            settings.palette.colors[idx] = *color;
+        }
+        settings.palette.colors[0] = self.background.unwrap_or(settings.palette.off());
+        settings.palette.colors[1] = self.foreground.unwrap_or(settings.palette.on());
+        if self.theme.is_some() || !self.palette.is_empty() || self.background.is_some() || self.foreground.is_some() {
+            sources.set("palette", source);
+        }
+        if let Some(renderer) = self.renderer {
+            settings.renderer = renderer;
+            sources.set("renderer", source);
+        }
+    }
+}
+
+/// `quirks = "schip"`, or a table with an optional profile and fields.
+fn parse_quirks(parsed: &mut Table, value: &Value) -> Result<(), String> {
+    let profile_error = |name: &str| format!("unknown quirk profile '{}', expected one of {}", name, PROFILE_NAMES.join(", "));
+    let table = match value {
+        Value::String(name) => {
+            parsed.quirk_profile = Some(Quirks::from_name(name).ok_or_else(|| profile_error(name))?);
+            return Ok(());
+        }
+        Value::Table(table) => table,
+        _ => return Err("expected a profile name or a table".to_string())
+    };
+
+    for (name, value) in table {
+        let text = match value {
+            Value::String(text) => text.clone(),
+            Value::Boolean(flag) => flag.to_string(),
+            _ => return Err(format!("{}: expected a string or a boolean", name))
+        };
+        if name == "profile" {
+            parsed.quirk_profile = Some(Quirks::from_name(&text).ok_or_else(|| profile_error(&text))?);
+        } else if Quirks::default().set(name, &text).is_some() {
+            parsed.quirk_fields.push((name.clone(), text));
+        } else {
+            return Err(format!("invalid quirk {} = {}, quirks are profile, {}", name, value, FIELD_NAMES.join(", ")));
+        }
+    }
+    Ok(())
+}
+
+const COLOR_ERROR: &str = "expected #rrggbb or a colour name such as white or dark_green";
+
+fn positive<T: std::convert::TryFrom<i64>>(value: &Value) -> Option<T> {
+    value.as_integer().filter(|value| *value > 0).and_then(|value| T::try_from(value).ok())
+}
+
+fn hex(hash: &RomHash) -> String {
+    hash.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::savestate::rom_hash;
+
+    const CONFIG: &str = r##"
+        ipf = 12
+        keymap = "hex"
+        foreground = "#33FF66"
+
+        [quirks]
+        profile = "schip"
+        key_on_press = false
+
+        [rom.a9993e364706816aba3e25717850c26c9cd0d89d]
+        cpu-hz = 500
+        background = "dark_grey"
+        quirks = { memory_increment = "x" }
+    "##;
+
+    #[test]
+    fn settings_test() {
+        let config = Config::parse(CONFIG).unwrap();
+
+        let global = config.settings(Some(&rom_hash(b"other")));
+        assert_eq!(12, global.ipf);
+        assert_eq!(Keymap::HEX, global.keymap);
+        assert_eq!(Color::Rgb { r: 0x33, g: 0xFF, b: 0x66 }, global.palette.on());
+        assert_eq!(Quirks { key_on_press: false, ..Quirks::SCHIP }, global.quirks);
+
+        let rom = config.settings(Some(&rom_hash(b"abc")));
+        assert_eq!(Some(500), rom.cpu_hz);
+        assert_eq!(Color::DarkGrey, rom.palette.off());
+        assert_eq!(Quirks { key_on_press: false, memory_increment: crate::quirks::MemoryIncrement::X, ..Quirks::SCHIP }, rom.quirks);
+    }
+
+    #[test]
+    fn palette_test() {
+        let config = Config::parse(r##"
+            theme = "lcd"
+            palette = ["black", "white", "red"]
+            foreground = "#33ff66"
+
+            [rom.a9993e364706816aba3e25717850c26c9cd0d89d]
+            theme = "amber"
+        "##).unwrap();
+
+        let global = config.settings(None).palette;
+        assert_eq!([Color::Black, Color::Rgb { r: 0x33, g: 0xFF, b: 0x66 }, Color::Red, Palette::LCD.colors[3]], global.colors);
+        // The global colours are part of the palette the rom theme replaces
+        assert_eq!(Palette::AMBER, config.settings(Some(&rom_hash(b"abc"))).palette);
+
+        // Off, on, the second XO-CHIP plane and both planes
+        let palette = Config::parse("palette = [\"black\", \"white\", \"#ff0000\", \"dark_cyan\"]").unwrap().settings(None).palette;
+        assert_eq!([Color::Black, Color::White, Color::Rgb { r: 0xFF, g: 0, b: 0 }, Color::DarkCyan], palette.colors);
+        assert!(palette.to_ansi_256().colors.iter().all(|color| !matches!(color, Color::Rgb { .. })));
+        assert!(Config::parse("palette = [\"black\", \"white\", \"red\", \"blue\", \"green\"]").is_err());
+    }
+
+    #[test]
+    fn show_test() {
+        let settings = Config::parse(CONFIG).unwrap().settings(Some(&rom_hash(b"abc")));
+        assert_eq!(settings, Config::parse(&settings.to_string()).unwrap().settings(None));
+        assert_eq!(Settings::default(), Config::parse(&Settings::default().to_string()).unwrap().settings(None));
+
+        let settings = Settings { palette: Palette::GREEN, ..Settings::default() };
+        assert!(settings.to_string().contains("theme = \"green\""));
+        assert_eq!(settings, Config::parse(&settings.to_string()).unwrap().settings(None));
+
+        let keymap = Keymap::parse("1 2 3 4 q w e r a s d f z x \\ \"").unwrap();
+        let settings = Settings { keymap, ..Settings::default() };
+        assert_eq!(keymap, Config::parse(&settings.to_string()).unwrap().settings(None).keymap);
+    }
+
+    #[test]
+    fn sources_test() {
+        let config = Config::parse(CONFIG).unwrap();
+        let files = RomFiles { quirks: Some(Quirks::CHIP48), keymap: Some(Keymap::AZERTY) };
+
+        // The rom table goes over the files next to the rom
+        let (settings, sources) = config.rom_settings(Some(&rom_hash(b"abc")), &files);
+        assert_eq!(Keymap::AZERTY, settings.keymap);
+        assert_eq!(Quirks { memory_increment: crate::quirks::MemoryIncrement::X, ..Quirks::CHIP48 }, settings.quirks);
+        assert_eq!(
+            [Source::Config, Source::RomTable, Source::Default, Source::RomFile, Source::RomTable, Source::RomFile, Source::RomTable],
+            ["ipf", "cpu-hz", "hz", "keymap", "palette", "shift_vx", "memory_increment"].map(|key| sources.get(key))
+        );
+
+        let shown = settings.show(&sources);
+        assert!(shown.contains("ipf = 12  # config\n"));
+        assert!(shown.contains("keymap = \"azerty\"  # file next to the rom\n"));
+        assert!(shown.contains("memory_increment = \"x\"  # [rom.<sha1>] table\n"));
+        assert_eq!(settings, Config::parse(&shown).unwrap().settings(None));
+
+        // A keymap layout spans lines, the comment follows the closing quotes
+        let keymap = Keymap::parse("1 2 3 4 q w e r a s d f z x c \"").unwrap();
+        let (settings, sources) = config.rom_settings(None, &RomFiles { quirks: None, keymap: Some(keymap) });
+        assert_eq!(settings, Config::parse(&settings.show(&sources)).unwrap().settings(None));
+    }
+
+    #[test]
+    fn errors_test() {
+        assert!(Config::parse("ipf = 0").is_err());
+        assert!(Config::parse("ipf = \"fast\"").is_err());
+        assert!(Config::parse("turbo = true").is_err());
+        assert!(Config::parse("foreground = \"#12345\"").is_err());
+        assert!(Config::parse("theme = \"sepia\"").is_err());
+        assert!(Config::parse("palette = []").is_err());
+        assert!(Config::parse("palette = [\"black\", \"pink\"]").is_err());
+        assert!(Config::parse("quirks = \"nes\"").is_err());
+        assert!(Config::parse("[quirks]\nshift_vx = 1").is_err());
+        assert!(Config::parse("[rom.pong]\nipf = 1").is_err());
+        assert_eq!(Err("rom.a9993e364706816aba3e25717850c26c9cd0d89d.hz: expected a number greater than 0".to_string()),
+            Config::parse("[rom.a9993e364706816aba3e25717850c26c9cd0d89d]\nhz = -1"));
+        assert!(Config::parse("scale = 511").is_ok());
+        assert_eq!(Err("rom.a9993e364706816aba3e25717850c26c9cd0d89d.scale: expected a number from 1 to 511".to_string()),
+            Config::parse("[rom.a9993e364706816aba3e25717850c26c9cd0d89d]\nscale = 520"));
+    }
+}
@@ -1,68 +1,162 @@
-use std::collections::VecDeque;
-
-const SCREEN_SIZE: usize = 64 * 32;
+pub const MEMORY_SIZE: usize = 4096;
+pub const PROGRAM_START: u16 = 0x200;
+// Default depth of the call stack, as on SUPER-CHIP
+pub const STACK_SIZE: usize = 16;
+// Deepest call stack that can be configured
+pub const MAX_STACK_SIZE: usize = 64;
+
+/// Fx0A in progress, the CPU stalls until it completes
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct KeyWait {
+    // Register receiving the key
+    pub x: u8,
+    // Keys held on the previous step, a press is a key missing from it
+    pub held: u16,
+    // Key pressed, completing when it is released
+    pub pressed: Option<u8>
+}
 
+#[derive(Clone, Copy, PartialEq)]
 pub struct CPU {
     // heap
-    pub memory: Vec<u8>,
+    pub memory: [u8; MEMORY_SIZE],
     pub pc: u16,
 
-    // stack
-    pub stack: VecDeque<u16>,
+    // stack, sp is the number of entries in use
+    pub stack: [u16; MAX_STACK_SIZE],
     pub sp: u8,
-    
+    pub stack_size: u8,
+
     // Regsiters
-    pub registers: Vec<u8>,
+    pub registers: [u8; 16],
     pub i: u16,
 
     // Screen 64*32
-    pub screen: Vec<bool>,
+    pub screen: Framebuffer,
 
-    // key pressed
-    pub key_pressed: Option<u16>,
+    // Keypad, bit n set while key n is held
+    pub keys: u16,
+    pub key_wait: Option<KeyWait>,
 
     // Delay timer
-    pub dt: u16,
+    pub dt: u8,
     // Sound timer
-    pub st: u16
+    pub st: u8,
+
+    // Set on every timer tick, DRW waits for it with the display wait quirk
+    pub vblank: bool,
+
+    // Lowest and highest address written by update_memory since it was last
+    // taken, used to invalidate the instruction cache
+    pub memory_writes: Option<(usize, usize)>,
+
+    pub quirks: Quirks,
+
+    // Source of Cxkk, randomly seeded unless set with Chip8::set_seed
+    pub rng: Rng,
+
+    // Checked on memory, register and I accesses, the first one to trigger
+    // during an instruction is kept in watch_hit until the next step
+    pub watchpoints: Watchpoints,
+    pub watch_hit: Option<WatchHit>
 }
 
+use crate::error::Chip8Error;
+use crate::framebuffer::Framebuffer;
 use crate::graphic;
+use crate::quirks::Quirks;
+use crate::rng::Rng;
+use crate::watchpoints::{Access, WatchHit, WatchTarget, Watchpoints};
 
 impl CPU {
 
     pub fn new() -> Self {
         let mut cpu = CPU {
-            memory: vec![0; 4096],
-            pc: 0x200,
-            stack: VecDeque::new(),
+            memory: [0; MEMORY_SIZE],
+            pc: PROGRAM_START,
+            stack: [0; MAX_STACK_SIZE],
             sp: 0,
-            registers: vec![0; 16],
+            stack_size: STACK_SIZE as u8,
+            registers: [0; 16],
             i: 0,
-            screen: vec![false; SCREEN_SIZE],
-            key_pressed: None,
+            screen: Framebuffer::new(),
+            keys: 0,
+            key_wait: None,
             dt: 0,
-            st: 0
+            st: 0,
+            vblank: true,
+            memory_writes: None,
+            quirks: Quirks::default(),
+            rng: Rng::new(rand::random()),
+            watchpoints: Watchpoints::new(),
+            watch_hit: None
         };
 
-        cpu.memory = graphic::get_sprites().to_vec();
-        cpu.memory.resize(0x200, 0);
+        let sprites = graphic::get_sprites();
+        cpu.memory[..sprites.len()].copy_from_slice(sprites);
 
         cpu
     }
 
     pub fn clear_screen(&mut self) {
-        self.screen = vec![false; SCREEN_SIZE];
+        self.screen.clear();
+    }
+
+    pub fn push_stack(&mut self, addr: u16) -> Result<(), Chip8Error> {
+        if self.sp >= self.stack_size {
+            return Err(Chip8Error::StackOverflow);
+        }
+        self.stack[usize::from(self.sp)] = addr;
+        self.sp += 1;
+        Ok(())
     }
 
-    pub fn get_next_opcode(&self) -> u16 {
+    pub fn pop_stack(&mut self) -> Result<u16, Chip8Error> {
+        if self.sp == 0 {
+            return Err(Chip8Error::StackUnderflow);
+        }
+        self.sp -= 1;
+        Ok(self.stack[usize::from(self.sp)])
+    }
+
+    pub fn get_next_opcode(&self) -> Result<u16, Chip8Error> {
         let idx = usize::from(self.pc);
-        let op_code =  ((self.memory[idx] as u16) << 8) | self.memory[idx + 1] as u16;
-        op_code
+        Ok(((self.read_memory(idx)? as u16) << 8) | self.read_memory(idx + 1)? as u16)
+    }
+
+    pub fn read_memory(&self, idx: usize) -> Result<u8, Chip8Error> {
+        match self.memory.get(idx) {
+            Some(value) => Ok(*value),
+            None => Err(Chip8Error::MemoryOutOfBounds { addr: idx })
+        }
     }
 
-    pub fn update_memory(&mut self, idx: u16, value: u8) {
-        self.memory[usize::from(idx)] = value;
+    /// Reads a byte on behalf of an instruction, unlike `read_memory` this
+    /// triggers read watchpoints.
+    pub fn load_memory(&mut self, idx: usize) -> Result<u8, Chip8Error> {
+        let value = self.read_memory(idx)?;
+        if self.watchpoints.watches_read(idx) {
+            self.watch(WatchTarget::Memory { start: idx as u16, end: idx as u16 }, Access::Read, value.into(), value.into());
+        }
+        Ok(value)
+    }
+
+    pub fn update_memory(&mut self, idx: usize, value: u8) -> Result<(), Chip8Error> {
+        match self.memory.get_mut(idx) {
+            Some(byte) => {
+                let old = *byte;
+                *byte = value;
+                if self.watchpoints.watches_write(idx) {
+                    self.watch(WatchTarget::Memory { start: idx as u16, end: idx as u16 }, Access::Write, old.into(), value.into());
+                }
+                self.memory_writes = match self.memory_writes {
+                    Some((start, end)) => Some((start.min(idx), end.max(idx))),
+                    None => Some((idx, idx))
+                };
+                Ok(())
+            }
+            None => Err(Chip8Error::MemoryOutOfBounds { addr: idx })
+        }
     }
 
     pub fn get_reg(&self, reg_number: u8) -> u8 {
@@ -70,7 +164,26 @@ impl CPU {
     }
 
     pub fn set_register_value(&mut self, reg_number: u8, value: u8) {
+        let old = self.get_reg(reg_number);
         self.registers[usize::from(reg_number)] = value;
+        if self.watchpoints.watches_register(reg_number) {
+            self.watch(WatchTarget::Register(reg_number), Access::Write, old.into(), value.into());
+        }
+    }
+
+    pub fn set_i(&mut self, value: u16) {
+        let old = self.i;
+        self.i = value;
+        if self.watchpoints.watches_i() {
+            self.watch(WatchTarget::I, Access::Write, old, value);
+        }
+    }
+
+    // Only the first access of an instruction is reported
+    fn watch(&mut self, target: WatchTarget, access: Access, old: u16, new: u16) {
+        if self.watch_hit.is_none() {
+            self.watch_hit = Some(WatchHit { pc: self.pc, target, access, old, new });
+        }
     }
 
     pub fn set_reg_f(&mut self, value: u8) {
@@ -85,6 +198,37 @@ impl CPU {
         if self.st > 0 {
             self.st -= 1;
         }
+
+        self.vblank = true;
+    }
+
+    /// Checks the keypad for the Fx0A in progress, called instead of
+    /// executing an instruction while there is one.
+    pub fn poll_key_wait(&mut self) {
+        let mut wait = match self.key_wait {
+            Some(wait) => wait,
+            None => return
+        };
+
+        let pressed = self.keys & !wait.held;
+        let completed = match wait.pressed {
+            None if pressed != 0 => {
+                let key = pressed.trailing_zeros() as u8;
+                wait.pressed = Some(key);
+                self.quirks.key_on_press
+            }
+            Some(key) => self.keys & (1 << key) == 0,
+            None => false
+        };
+        wait.held = self.keys;
+
+        match (completed, wait.pressed) {
+            (true, Some(key)) => {
+                self.set_register_value(wait.x, key);
+                self.key_wait = None;
+            }
+            _ => self.key_wait = Some(wait)
+        }
     }
 
     pub fn dump_registers(&self) {
@@ -94,4 +238,10 @@ impl CPU {
         print!(" -- [i] {}", self.i);
         println!();
     }
-}
\ No newline at end of file
+}
+
+impl Default for CPU {
+    fn default() -> Self {
+        Self::new()
+    }
+}
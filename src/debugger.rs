@@ -0,0 +1,397 @@
+use std::collections::BTreeSet;
+
+use crate::chip8::Chip8;
+use crate::error::Chip8Error;
+use crate::instructions::Instruction;
+use crate::opcodes;
+use crate::watchpoints::{Access, WatchTarget};
+
+/// Instructions shown before and after the PC in the panel
+const CONTEXT: u16 = 5;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum State {
+    Paused,
+    Running,
+    RunTo(u16),
+    // Runs until the CALL at the PC returns, at the same stack depth in case it recurses
+    StepOver { addr: u16, sp: u8 }
+}
+
+/// Decides when execution stops: breakpoints on the PC, watchpoints,
+/// stepping and running to an address.
+///
+/// While running, `should_break` is checked before every instruction, see
+/// `Scheduler::run_until`.
+pub struct Debugger {
+    breakpoints: BTreeSet<u16>,
+    state: State,
+    // Set when resuming, so a breakpoint on the PC does not stop right away
+    resuming: bool
+}
+
+/// Debugger commands typed at the `:` prompt.
+#[derive(Debug, PartialEq)]
+pub enum Command {
+    Step,
+    StepOver,
+    Continue,
+    RunTo(u16),
+    Break(u16),
+    Delete(u16),
+    Watch(WatchTarget, Access),
+    Unwatch(WatchTarget)
+}
+
+impl Command {
+    /// `step`, `next`, `continue`, `until <addr>`, `break <addr>` or
+    /// `delete <addr>`, or their first letter. Addresses are hexadecimal.
+    ///
+    /// `watch <target> [r|w|rw]` and `unwatch <target>` set and remove
+    /// watchpoints, the target is `Vx`, `I`, an address or a range like
+    /// `3A0-3A9`. Watchpoints default to writes, only memory can be watched
+    /// for reads.
+    pub fn parse(text: &str) -> Result<Command, String> {
+        let mut words = text.split_whitespace();
+        let command = words.next().unwrap_or("");
+        let mut arg = || words.next().ok_or_else(|| format!("{} needs an address", command));
+
+        let parsed = match command {
+            "s" | "step" => Command::Step,
+            "n" | "next" => Command::StepOver,
+            "c" | "continue" => Command::Continue,
+            "u" | "until" => Command::RunTo(parse_addr(arg()?)?),
+            "b" | "break" => Command::Break(parse_addr(arg()?)?),
+            "d" | "delete" => Command::Delete(parse_addr(arg()?)?),
+            "w" | "watch" => {
+                let target = parse_target(arg()?)?;
+                let access = match words.next() {
+                    None | Some("w") => Access::Write,
+                    Some("r") => Access::Read,
+                    Some("rw") => Access::ReadWrite,
+                    Some(access) => return Err(format!("invalid access '{}', expected r, w or rw", access))
+                };
+                // Reads of registers and I are not tracked
+                if access != Access::Write && !matches!(target, WatchTarget::Memory { .. }) {
+                    return Err("registers and I can only be watched for writes".to_string());
+                }
+                Command::Watch(target, access)
+            }
+            "uw" | "unwatch" => Command::Unwatch(parse_target(arg()?)?),
+            "" => return Err("empty command".to_string()),
+            _ => return Err(format!("unknown command '{}'", command))
+        };
+
+        match words.next() {
+            Some(arg) => Err(format!("unexpected argument '{}'", arg)),
+            None => Ok(parsed)
+        }
+    }
+}
+
+fn parse_addr(arg: &str) -> Result<u16, String> {
+    let digits = arg.trim_start_matches("0x").trim_start_matches("0X");
+    u16::from_str_radix(digits, 16)
+        .ok()
+        .filter(|addr| *addr < 0x1000)
+        .ok_or_else(|| format!("invalid address '{}'", arg))
+}
+
+fn parse_target(arg: &str) -> Result<WatchTarget, String> {
+    let upper = arg.to_uppercase();
+    if upper == "I" {
+        return Ok(WatchTarget::I);
+    }
+    if upper.len() == 2 && upper.starts_with('V') {
+        if let Ok(x) = u8::from_str_radix(&upper[1..], 16) {
+            return Ok(WatchTarget::Register(x));
+        }
+    }
+
+    let (start, end) = match arg.find('-') {
+        Some(dash) => (parse_addr(&arg[..dash])?, parse_addr(&arg[dash + 1..])?),
+        None => (parse_addr(arg)?, parse_addr(arg)?)
+    };
+    if start > end {
+        return Err(format!("invalid range '{}'", arg));
+    }
+    Ok(WatchTarget::Memory { start, end })
+}
+
+impl Debugger {
+    /// A debugger starting paused, without breakpoints.
+    pub fn new() -> Self {
+        Debugger {
+            breakpoints: BTreeSet::new(),
+            state: State::Paused,
+            resuming: false
+        }
+    }
+
+    pub fn is_paused(&self) -> bool {
+        self.state == State::Paused
+    }
+
+    pub fn pause(&mut self) {
+        self.state = State::Paused;
+    }
+
+    /// Runs until a breakpoint is hit.
+    pub fn resume(&mut self) {
+        self.state = State::Running;
+        self.resuming = true;
+    }
+
+    /// Runs until the PC reaches `addr` or a breakpoint is hit.
+    pub fn run_to(&mut self, addr: u16) {
+        self.state = State::RunTo(addr);
+        self.resuming = true;
+    }
+
+    /// Executes the instruction at the PC and stays paused.
+    pub fn step(&mut self, chip8: &mut Chip8) -> Result<bool, Chip8Error> {
+        self.pause();
+        chip8.step()
+    }
+
+    /// Like `step`, but a CALL runs until the subroutine returns.
+    pub fn step_over(&mut self, chip8: &mut Chip8) -> Result<bool, Chip8Error> {
+        let cpu = chip8.cpu();
+        match cpu.get_next_opcode().ok().and_then(opcodes::decode) {
+            Some(Instruction::Call { .. }) => {
+                self.state = State::StepOver { addr: cpu.pc + 2, sp: cpu.sp };
+                self.resuming = true;
+                Ok(false)
+            }
+            _ => self.step(chip8)
+        }
+    }
+
+    /// Adds a breakpoint on `addr`, or removes it if there was one.
+    /// Returns true when the breakpoint is now set.
+    pub fn toggle_breakpoint(&mut self, addr: u16) -> bool {
+        if self.breakpoints.remove(&addr) {
+            false
+        } else {
+            self.breakpoints.insert(addr)
+        }
+    }
+
+    pub fn set_breakpoint(&mut self, addr: u16) {
+        self.breakpoints.insert(addr);
+    }
+
+    pub fn remove_breakpoint(&mut self, addr: u16) -> bool {
+        self.breakpoints.remove(&addr)
+    }
+
+    pub fn breakpoints(&self) -> &BTreeSet<u16> {
+        &self.breakpoints
+    }
+
+    /// Applies a command typed at the prompt, returns true when the screen has changed.
+    pub fn execute(&mut self, chip8: &mut Chip8, command: Command) -> Result<bool, Chip8Error> {
+        match command {
+            Command::Step => return self.step(chip8),
+            Command::StepOver => return self.step_over(chip8),
+            Command::Continue => self.resume(),
+            Command::RunTo(addr) => self.run_to(addr),
+            Command::Break(addr) => self.set_breakpoint(addr),
+            Command::Delete(addr) => {
+                self.remove_breakpoint(addr);
+            }
+            Command::Watch(target, access) => chip8.watchpoints_mut().add(target, access),
+            Command::Unwatch(target) => chip8.watchpoints_mut().remove(target)
+        }
+        Ok(false)
+    }
+
+    /// Checked before each instruction, returns true when execution must
+    /// stop before the instruction at the PC. Stopping pauses the debugger.
+    pub fn should_break(&mut self, chip8: &Chip8) -> bool {
+        let cpu = chip8.cpu();
+        if self.state == State::Paused {
+            return true;
+        }
+        if self.resuming {
+            self.resuming = false;
+            return false;
+        }
+
+        // The watchpoint was triggered by the instruction that just ran
+        let stop = chip8.watch_hit().is_some() || self.breakpoints.contains(&cpu.pc) || match self.state {
+            State::RunTo(addr) => cpu.pc == addr,
+            State::StepOver { addr, sp } => cpu.pc == addr && cpu.sp == sp,
+            _ => false
+        };
+        if stop {
+            self.pause();
+        }
+        stop
+    }
+
+    /// Lines of the side panel: registers, stack, timers and the
+    /// disassembly around the PC, `>` marks the PC and `*` breakpoints.
+    pub fn panel(&self, chip8: &Chip8) -> Vec<String> {
+        let cpu = chip8.cpu();
+        let mut lines = vec![
+            format!("PC {:#05X}  I {:#05X}  {}", cpu.pc, cpu.i, if self.is_paused() { "paused" } else { "running" }),
+            String::new()
+        ];
+
+        for row in cpu.registers.chunks(4).enumerate() {
+            let (row, registers) = row;
+            let registers: Vec<String> = registers.iter().enumerate()
+                .map(|(idx, value)| format!("V{:X} {:02X}", row * 4 + idx, value))
+                .collect();
+            lines.push(registers.join("  "));
+        }
+
+        lines.push(format!("DT {:02X}  ST {:02X}  SP {}", cpu.dt, cpu.st, cpu.sp));
+        let stack: Vec<String> = cpu.stack[..usize::from(cpu.sp)].iter().rev().map(|addr| format!("{:#05X}", addr)).collect();
+        lines.push(format!("Stack {}", stack.join(" ")));
+        lines.push(match (chip8.watch_hit(), cpu.key_wait) {
+            (Some(hit), _) => format!("Watch {}", hit),
+            (None, Some(wait)) => format!("Waiting for a key in V{:X}", wait.x),
+            (None, None) => String::new()
+        });
+
+        let start = cpu.pc.saturating_sub(CONTEXT * 2);
+        for addr in (start..=cpu.pc + CONTEXT * 2).step_by(2) {
+            let opcode = match (cpu.read_memory(usize::from(addr)), cpu.read_memory(usize::from(addr) + 1)) {
+                (Ok(high), Ok(low)) => u16::from(high) << 8 | u16::from(low),
+                _ => break
+            };
+            let marker = if addr == cpu.pc { '>' } else if self.breakpoints.contains(&addr) { '*' } else { ' ' };
+            let mnemonic = opcodes::decode(opcode).map(|instruction| instruction.to_string()).unwrap_or_default();
+            lines.push(format!("{} {:#05X}: {:04X}  {}", marker, addr, opcode, mnemonic));
+        }
+        lines
+    }
+}
+
+impl Default for Debugger {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::scheduler::Scheduler;
+    use std::time::Duration;
+
+    // 0x200: CALL 0x206 - JP 0x204 - 0x204: JP 0x204
+    // 0x206: LD V0, 0x01 - RET
+    const ROM: &[u8] = &[0x22, 0x06, 0x12, 0x04, 0x12, 0x04, 0x60, 0x01, 0x00, 0xEE];
+
+    fn chip8() -> Chip8 {
+        let mut chip8 = Chip8::new();
+        chip8.load_rom(ROM).unwrap();
+        chip8
+    }
+
+    fn run(debugger: &mut Debugger, chip8: &mut Chip8) {
+        let mut scheduler = Scheduler::new(600, 60);
+        scheduler.run_until(chip8, Duration::from_millis(100), |chip8| debugger.should_break(chip8)).unwrap();
+    }
+
+    #[test]
+    fn step_and_step_over_test() {
+        let mut debugger = Debugger::new();
+        let mut chip8 = chip8();
+
+        debugger.step(&mut chip8).unwrap();
+        assert_eq!(0x206, chip8.cpu().pc);
+
+        let mut chip8 = self::chip8();
+        debugger.step_over(&mut chip8).unwrap();
+        run(&mut debugger, &mut chip8);
+        assert!(debugger.is_paused());
+        assert_eq!(0x202, chip8.cpu().pc);
+        assert_eq!(0x01, chip8.cpu().registers[0]);
+    }
+
+    #[test]
+    fn breakpoints_test() {
+        let mut debugger = Debugger::new();
+        let mut chip8 = chip8();
+
+        // Paused, nothing runs
+        run(&mut debugger, &mut chip8);
+        assert_eq!(0x200, chip8.cpu().pc);
+
+        assert!(debugger.toggle_breakpoint(0x208));
+        debugger.resume();
+        run(&mut debugger, &mut chip8);
+        assert!(debugger.is_paused());
+        assert_eq!(0x208, chip8.cpu().pc);
+
+        // Resuming from a breakpoint goes past it
+        debugger.resume();
+        run(&mut debugger, &mut chip8);
+        assert!(!debugger.is_paused());
+        assert_eq!(0x204, chip8.cpu().pc);
+
+        assert!(!debugger.toggle_breakpoint(0x208));
+        assert!(debugger.breakpoints().is_empty());
+    }
+
+    #[test]
+    fn run_to_test() {
+        let mut debugger = Debugger::new();
+        let mut chip8 = chip8();
+
+        debugger.execute(&mut chip8, Command::RunTo(0x208)).unwrap();
+        run(&mut debugger, &mut chip8);
+        assert!(debugger.is_paused());
+        assert_eq!(0x208, chip8.cpu().pc);
+    }
+
+    #[test]
+    fn watchpoint_halts_test() {
+        let mut debugger = Debugger::new();
+        let mut chip8 = chip8();
+
+        debugger.execute(&mut chip8, Command::Watch(WatchTarget::Register(0), Access::Write)).unwrap();
+        debugger.resume();
+        run(&mut debugger, &mut chip8);
+        assert!(debugger.is_paused());
+        // Stopped after the LD V0 at 0x206 that triggered it
+        assert_eq!(0x208, chip8.cpu().pc);
+        assert_eq!(0x206, chip8.watch_hit().unwrap().pc);
+        assert!(debugger.panel(&chip8).contains(&"Watch write V0 0x00 -> 0x01 at 0x206".to_string()));
+    }
+
+    #[test]
+    fn parse_command_test() {
+        assert_eq!(Ok(Command::Step), Command::parse("s"));
+        assert_eq!(Ok(Command::StepOver), Command::parse(" next "));
+        assert_eq!(Ok(Command::Break(0x2A4)), Command::parse("b 2a4"));
+        assert_eq!(Ok(Command::RunTo(0x2A4)), Command::parse("until 0x2A4"));
+        assert!(Command::parse("b").is_err());
+        assert!(Command::parse("b 1000").is_err());
+        assert!(Command::parse("s 200").is_err());
+        assert!(Command::parse("jump").is_err());
+        assert_eq!(Ok(Command::Watch(WatchTarget::Memory { start: 0x3A0, end: 0x3A9 }, Access::ReadWrite)), Command::parse("watch 3a0-3a9 rw"));
+        assert_eq!(Ok(Command::Watch(WatchTarget::Register(0xF), Access::Write)), Command::parse("w vf"));
+        assert_eq!(Ok(Command::Unwatch(WatchTarget::I)), Command::parse("unwatch I"));
+        assert!(Command::parse("watch 3a9-3a0").is_err());
+        assert_eq!(Err("registers and I can only be watched for writes".to_string()), Command::parse("watch V3 r"));
+        assert!(Command::parse("watch I rw").is_err());
+        assert_eq!(Ok(Command::Watch(WatchTarget::I, Access::Write)), Command::parse("watch I w"));
+    }
+
+    #[test]
+    fn panel_test() {
+        let mut debugger = Debugger::new();
+        debugger.set_breakpoint(0x202);
+        let panel = debugger.panel(&chip8());
+
+        assert_eq!("PC 0x200  I 0x000  paused", panel[0]);
+        assert_eq!("V0 00  V1 00  V2 00  V3 00", panel[2]);
+        assert!(panel.contains(&"> 0x200: 2206  CALL 0x206".to_string()));
+        assert!(panel.contains(&"* 0x202: 1204  JP 0x204".to_string()));
+    }
+}
@@ -0,0 +1,311 @@
+use std::collections::BTreeMap;
+use std::fmt;
+use std::fmt::Write;
+
+use crate::cpu::PROGRAM_START;
+use crate::instructions::Instruction;
+use crate::opcodes;
+
+/// A rom split into code and data by following the control flow from 0x200.
+///
+/// Bytes never reached by a jump, call, skip or fall-through are treated as
+/// data. Jumps through `JP V0, addr` cannot be followed, code only reached
+/// that way shows up as data.
+pub struct Disassembly<'a> {
+    rom: &'a [u8],
+    // Offsets in the rom where a reachable instruction starts
+    code: Vec<bool>,
+    labels: BTreeMap<u16, String>
+}
+
+pub fn disassemble(rom: &[u8]) -> Disassembly<'_> {
+    let mut code = vec![false; rom.len()];
+    let mut jump_targets = Vec::new();
+    let mut call_targets = Vec::new();
+    let mut data_targets = Vec::new();
+
+    let mut pending = vec![PROGRAM_START];
+    while let Some(addr) = pending.pop() {
+        let instruction = match offset(rom, addr) {
+            Some(idx) if !code[idx] && idx + 1 < rom.len() => {
+                match opcodes::decode(u16::from(rom[idx]) << 8 | u16::from(rom[idx + 1])) {
+                    Some(instruction) => {
+                        code[idx] = true;
+                        instruction
+                    }
+                    None => continue
+                }
+            }
+            _ => continue
+        };
+
+        let next = addr + 2;
+        match instruction {
+            Instruction::Ret => {}
+            Instruction::Jp { addr } => {
+                jump_targets.push(addr);
+                pending.push(addr);
+            }
+            Instruction::Call { addr } => {
+                call_targets.push(addr);
+                pending.push(addr);
+                pending.push(next);
+            }
+            Instruction::SeByte { .. } | Instruction::SneByte { .. } | Instruction::Se { .. }
+            | Instruction::Sne { .. } | Instruction::Skp { .. } | Instruction::Sknp { .. } => {
+                pending.push(next);
+                pending.push(next + 2);
+            }
+            Instruction::JpV0 { addr } => jump_targets.push(addr),
+            Instruction::LdI { addr } => {
+                data_targets.push(addr);
+                pending.push(next);
+            }
+            _ => pending.push(next)
+        }
+    }
+
+    let mut disassembly = Disassembly {
+        rom,
+        code,
+        labels: BTreeMap::new()
+    };
+
+    // A label can only be placed where a line of the listing starts
+    let targets = data_targets.iter().map(|addr| (*addr, "data"))
+        .chain(jump_targets.iter().map(|addr| (*addr, "loc")))
+        .chain(call_targets.iter().map(|addr| (*addr, "sub")));
+    let line_starts = disassembly.lines().map(|(addr, _)| addr).collect::<Vec<_>>();
+    for (addr, prefix) in targets {
+        if line_starts.binary_search(&addr).is_ok() {
+            disassembly.labels.insert(addr, format!("{}_{:03X}", prefix, addr));
+        }
+    }
+
+    disassembly
+}
+
+fn offset(rom: &[u8], addr: u16) -> Option<usize> {
+    let idx = usize::from(addr.checked_sub(PROGRAM_START)?);
+    if idx < rom.len() {
+        Some(idx)
+    } else {
+        None
+    }
+}
+
+enum Line {
+    Code(u16, Instruction),
+    Data(u8)
+}
+
+impl<'a> Disassembly<'a> {
+    pub fn labels(&self) -> &BTreeMap<u16, String> {
+        &self.labels
+    }
+
+    /// True when an instruction reachable from 0x200 starts at `addr`.
+    pub fn is_code(&self, addr: u16) -> bool {
+        offset(self.rom, addr).map(|idx| self.code[idx]).unwrap_or(false)
+    }
+
+    fn lines(&self) -> impl Iterator<Item = (u16, Line)> + '_ {
+        let mut idx = 0;
+        std::iter::from_fn(move || {
+            if idx >= self.rom.len() {
+                return None;
+            }
+
+            let addr = PROGRAM_START + idx as u16;
+            if self.code[idx] {
+                let opcode = u16::from(self.rom[idx]) << 8 | u16::from(self.rom[idx + 1]);
+                idx += 2;
+                Some((addr, Line::Code(opcode, opcodes::decode(opcode).unwrap())))
+            } else {
+                idx += 1;
+                Some((addr, Line::Data(self.rom[idx - 1])))
+            }
+        })
+    }
+
+    /// Human-readable listing, one instruction or data byte per line:
+    /// `0x200: 6A02  LD VA, 0x02`
+    pub fn listing(&self) -> String {
+        let mut listing = String::new();
+        for (addr, line) in self.lines() {
+            if let Some(label) = self.labels.get(&addr) {
+                writeln!(listing, "{}:", label).unwrap();
+            }
+            match line {
+                Line::Code(opcode, instruction) => {
+                    writeln!(listing, "{:#05X}: {:04X}  {}", addr, opcode, mnemonic(&instruction, &self.labels)).unwrap();
+                }
+                Line::Data(byte) => {
+                    writeln!(listing, "{:#05X}: {:02X}    db {:#04X}  ; {}", addr, byte, byte, sprite_row(byte)).unwrap();
+                }
+            }
+        }
+        listing
+    }
+
+    /// Source that assembles back to the same rom with `chip8r asm`.
+    pub fn source(&self) -> String {
+        let mut source = String::new();
+        let mut data = Vec::new();
+        for (addr, line) in self.lines() {
+            let label = self.labels.get(&addr);
+            if let (Line::Data(byte), None) = (&line, label) {
+                data.push(*byte);
+                continue;
+            }
+
+            flush_data(&mut source, &mut data);
+            if let Some(label) = label {
+                writeln!(source, "{}:", label).unwrap();
+            }
+            match line {
+                Line::Code(_, instruction) => writeln!(source, "    {}", mnemonic(&instruction, &self.labels)).unwrap(),
+                Line::Data(byte) => data.push(byte)
+            }
+        }
+        flush_data(&mut source, &mut data);
+        source
+    }
+}
+
+fn flush_data(source: &mut String, data: &mut Vec<u8>) {
+    for chunk in data.chunks(8) {
+        let bytes: Vec<String> = chunk.iter().map(|byte| format!("{:#04X}", byte)).collect();
+        writeln!(source, "    db {}", bytes.join(", ")).unwrap();
+    }
+    data.clear();
+}
+
+// Data is often sprites, show the bits as pixels
+fn sprite_row(byte: u8) -> String {
+    (0..8).map(|bit| if byte & (0x80 >> bit) != 0 { '#' } else { '.' }).collect()
+}
+
+/// Assembly for an instruction, addresses with a label are replaced by it.
+pub fn mnemonic(instruction: &Instruction, labels: &BTreeMap<u16, String>) -> String {
+    let address = |addr: u16| match labels.get(&addr) {
+        Some(label) => label.clone(),
+        None => format!("{:#05X}", addr)
+    };
+
+    match *instruction {
+        Instruction::Sys { addr } => format!("SYS {}", address(addr)),
+        Instruction::Cls => "CLS".to_string(),
+        Instruction::Ret => "RET".to_string(),
+        Instruction::Jp { addr } => format!("JP {}", address(addr)),
+        Instruction::Call { addr } => format!("CALL {}", address(addr)),
+        Instruction::SeByte { x, kk } => format!("SE V{:X}, {:#04X}", x, kk),
+        Instruction::SneByte { x, kk } => format!("SNE V{:X}, {:#04X}", x, kk),
+        Instruction::Se { x, y } => format!("SE V{:X}, V{:X}", x, y),
+        Instruction::LdByte { x, kk } => format!("LD V{:X}, {:#04X}", x, kk),
+        Instruction::AddByte { x, kk } => format!("ADD V{:X}, {:#04X}", x, kk),
+        Instruction::Ld { x, y } => format!("LD V{:X}, V{:X}", x, y),
+        Instruction::Or { x, y } => format!("OR V{:X}, V{:X}", x, y),
+        Instruction::And { x, y } => format!("AND V{:X}, V{:X}", x, y),
+        Instruction::Xor { x, y } => format!("XOR V{:X}, V{:X}", x, y),
+        Instruction::Add { x, y } => format!("ADD V{:X}, V{:X}", x, y),
+        Instruction::Sub { x, y } => format!("SUB V{:X}, V{:X}", x, y),
+        Instruction::Shr { x, y } => format!("SHR V{:X}, V{:X}", x, y),
+        Instruction::Subn { x, y } => format!("SUBN V{:X}, V{:X}", x, y),
+        Instruction::Shl { x, y } => format!("SHL V{:X}, V{:X}", x, y),
+        Instruction::Sne { x, y } => format!("SNE V{:X}, V{:X}", x, y),
+        Instruction::LdI { addr } => format!("LD I, {}", address(addr)),
+        Instruction::JpV0 { addr } => format!("JP V0, {}", address(addr)),
+        Instruction::Rnd { x, kk } => format!("RND V{:X}, {:#04X}", x, kk),
+        Instruction::Drw { x, y, n } => format!("DRW V{:X}, V{:X}, {}", x, y, n),
+        Instruction::Skp { x } => format!("SKP V{:X}", x),
+        Instruction::Sknp { x } => format!("SKNP V{:X}", x),
+        Instruction::LdVxDt { x } => format!("LD V{:X}, DT", x),
+        Instruction::LdVxK { x } => format!("LD V{:X}, K", x),
+        Instruction::LdDtVx { x } => format!("LD DT, V{:X}", x),
+        Instruction::LdStVx { x } => format!("LD ST, V{:X}", x),
+        Instruction::AddI { x } => format!("ADD I, V{:X}", x),
+        Instruction::LdF { x } => format!("LD F, V{:X}", x),
+        Instruction::LdB { x } => format!("LD B, V{:X}", x),
+        Instruction::LdIVx { x } => format!("LD [I], V{:X}", x),
+        Instruction::LdVxI { x } => format!("LD V{:X}, [I]", x)
+    }
+}
+
+impl fmt::Display for Instruction {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(&mnemonic(self, &BTreeMap::new()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // 0x200: LD I, 0x20A - CALL 0x208 - JP 0x206
+    // 0x206: JP 0x206
+    // 0x208: RET
+    // 0x20A: sprite data
+    const ROM: &[u8] = &[0xA2, 0x0A, 0x22, 0x08, 0x12, 0x06, 0x12, 0x06, 0x00, 0xEE, 0xF0, 0x90];
+
+    #[test]
+    fn code_and_data_test() {
+        let disassembly = disassemble(ROM);
+
+        for addr in (0x200..0x20A).step_by(2) {
+            assert!(disassembly.is_code(addr), "{:#05X} should be code", addr);
+        }
+        assert!(!disassembly.is_code(0x20A));
+        assert!(!disassembly.is_code(0x20B));
+    }
+
+    #[test]
+    fn listing_test() {
+        let listing = disassemble(ROM).listing();
+
+        assert_eq!("0x200: A20A  LD I, data_20A
+0x202: 2208  CALL sub_208
+0x204: 1206  JP loc_206
+loc_206:
+0x206: 1206  JP loc_206
+sub_208:
+0x208: 00EE  RET
+data_20A:
+0x20A: F0    db 0xF0  ; ####....
+0x20B: 90    db 0x90  ; #..#....
+", listing);
+    }
+
+    #[test]
+    fn source_test() {
+        let source = disassemble(ROM).source();
+
+        assert_eq!("    LD I, data_20A
+    CALL sub_208
+    JP loc_206
+loc_206:
+    JP loc_206
+sub_208:
+    RET
+data_20A:
+    db 0xF0, 0x90
+", source);
+    }
+
+    #[test]
+    fn skips_follow_both_branches_test() {
+        // SE V0, 0x00 - JP 0x200 - LD V1, 0x01 - then 0xFF data
+        let disassembly = disassemble(&[0x30, 0x00, 0x12, 0x00, 0x61, 0x01, 0xFF]);
+
+        assert!(disassembly.is_code(0x202));
+        assert!(disassembly.is_code(0x204));
+        assert!(!disassembly.is_code(0x206));
+    }
+
+    #[test]
+    fn display_instruction_test() {
+        assert_eq!("LD VA, 0x02", Instruction::LdByte { x: 0xA, kk: 0x02 }.to_string());
+        assert_eq!("DRW V0, V1, 5", Instruction::Drw { x: 0, y: 1, n: 5 }.to_string());
+        assert_eq!("LD [I], VF", Instruction::LdIVx { x: 0xF }.to_string());
+    }
+}
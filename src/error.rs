@@ -0,0 +1,31 @@
+use std::error::Error;
+use std::fmt;
+
+/// Faults raised while loading or executing a program
+#[derive(Debug, Clone, PartialEq)]
+pub enum Chip8Error {
+    // RET with an empty call stack
+    StackUnderflow,
+    // CALL with a full call stack
+    StackOverflow,
+    // The instruction at `addr` cannot be decoded
+    UnknownOpcode { addr: u16, opcode: u16 },
+    // Read or write outside of the 4 KiB of memory
+    MemoryOutOfBounds { addr: usize },
+    // The rom does not fit in memory after 0x200
+    RomTooLarge { size: usize, max: usize }
+}
+
+impl fmt::Display for Chip8Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Chip8Error::StackUnderflow => write!(f, "stack underflow"),
+            Chip8Error::StackOverflow => write!(f, "stack overflow"),
+            Chip8Error::UnknownOpcode { addr, opcode } => write!(f, "unknown opcode {:04X} at {:#05X}", opcode, addr),
+            Chip8Error::MemoryOutOfBounds { addr } => write!(f, "memory access out of bounds at {:#05X}", addr),
+            Chip8Error::RomTooLarge { size, max } => write!(f, "rom is {} bytes, the maximum is {}", size, max)
+        }
+    }
+}
+
+impl Error for Chip8Error {}
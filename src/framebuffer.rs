@@ -0,0 +1,101 @@
+use crate::graphic::{HEIGHT, WIDTH};
+
+/// The 64x32 monochrome screen, one bit per pixel.
+///
+/// Each row is packed in a `u64` with the leftmost pixel in the most
+/// significant bit, so the whole screen is 256 bytes and `Copy`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Framebuffer {
+    rows: [u64; HEIGHT as usize]
+}
+
+impl Framebuffer {
+    pub fn new() -> Self {
+        Framebuffer {
+            rows: [0; HEIGHT as usize]
+        }
+    }
+
+    pub fn width(&self) -> u16 {
+        WIDTH
+    }
+
+    pub fn height(&self) -> u16 {
+        HEIGHT
+    }
+
+    pub fn clear(&mut self) {
+        self.rows = [0; HEIGHT as usize];
+    }
+
+    /// Panics if the pixel is outside of the screen.
+    pub fn get(&self, x: u16, y: u16) -> bool {
+        self.rows[usize::from(y)] & Self::mask(x) != 0
+    }
+
+    /// Panics if the pixel is outside of the screen.
+    pub fn set(&mut self, x: u16, y: u16, on: bool) {
+        if on {
+            self.rows[usize::from(y)] |= Self::mask(x);
+        } else {
+            self.rows[usize::from(y)] &= !Self::mask(x);
+        }
+    }
+
+    /// Flips a pixel and returns its previous value.
+    pub fn toggle(&mut self, x: u16, y: u16) -> bool {
+        let was_on = self.get(x, y);
+        self.rows[usize::from(y)] ^= Self::mask(x);
+        was_on
+    }
+
+    /// A row of pixels, leftmost pixel in the most significant bit.
+    pub fn row(&self, y: u16) -> u64 {
+        self.rows[usize::from(y)]
+    }
+
+    pub fn set_row(&mut self, y: u16, row: u64) {
+        self.rows[usize::from(y)] = row;
+    }
+
+    /// Number of pixels switched on.
+    pub fn count_on(&self) -> u32 {
+        self.rows.iter().map(|row| row.count_ones()).sum()
+    }
+
+    fn mask(x: u16) -> u64 {
+        assert!(x < WIDTH, "x {} is outside of the screen", x);
+        1 << (WIDTH - 1 - x)
+    }
+}
+
+impl Default for Framebuffer {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn set_and_toggle_test() {
+        let mut framebuffer = Framebuffer::new();
+
+        framebuffer.set(0, 0, true);
+        framebuffer.set(63, 31, true);
+        assert!(framebuffer.get(0, 0));
+        assert!(framebuffer.get(63, 31));
+        assert_eq!(1 << 63, framebuffer.row(0));
+        assert_eq!(1, framebuffer.row(31));
+
+        assert!(framebuffer.toggle(0, 0));
+        assert!(!framebuffer.toggle(1, 0));
+        assert!(!framebuffer.get(0, 0));
+        assert_eq!(2, framebuffer.count_on());
+
+        framebuffer.clear();
+        assert_eq!(0, framebuffer.count_on());
+    }
+}
@@ -1,9 +1,16 @@
 use std::io::Stdout;
 use std::io::Write;
 
+use crate::framebuffer::Framebuffer;
+use crate::palette::{self, Palette};
+use crate::renderer::Renderer;
+
 pub const WIDTH: u16 = 64;
 pub const HEIGHT: u16 = 32;
 
+/// Largest scale, the widest mode then still counts its cells in 16 bits
+pub const MAX_SCALE: u16 = u16::MAX / (WIDTH * 2);
+
 const SPRITES: &[u8] = &[
     0xF0, 0x90, 0x90, 0x90, 0xF0, // 0
     0x20, 0x60, 0x20, 0x20, 0x70, // 1
@@ -31,96 +38,429 @@ pub fn get_sprites() -> &'static [u8]  {
 use std::io::stdout;
 use crossterm::{
     ExecutableCommand, QueueableCommand,
-    terminal, cursor, style::{self, Colorize}
+    terminal, cursor, style::{self, Color}
 };
 
-pub struct Display {
-    stdout: Stdout
+// Unchanged cells between two changed ones below this are printed again
+// rather than moving the cursor over them, a move costs up to 10 bytes
+const MIN_GAP: usize = 4;
+
+/// How pixels are drawn with terminal characters, from the largest to
+/// the densest
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum RenderMode {
+    // A pixel is a block of full cells, two columns per row
+    Block,
+    // Two pixel rows per cell with ▀ and ▄
+    HalfBlock,
+    // 2x4 pixels per cell with braille patterns
+    Braille
+}
+
+impl RenderMode {
+    pub const NAMES: &'static [&'static str] = &["block", "half", "braille"];
+
+    pub fn from_name(name: &str) -> Option<RenderMode> {
+        match name.trim().to_lowercase().as_str() {
+            "block" => Some(RenderMode::Block),
+            "half" => Some(RenderMode::HalfBlock),
+            "braille" => Some(RenderMode::Braille),
+            _ => None
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            RenderMode::Block => "block",
+            RenderMode::HalfBlock => "half",
+            RenderMode::Braille => "braille"
+        }
+    }
+
+    /// The next denser mode, None for the densest.
+    fn denser(&self) -> Option<RenderMode> {
+        match self {
+            RenderMode::Block => Some(RenderMode::HalfBlock),
+            RenderMode::HalfBlock => Some(RenderMode::Braille),
+            RenderMode::Braille => None
+        }
+    }
+
+    /// Terminal columns and rows taken by a screen, without the border.
+    /// Each pixel is `scale` by `scale` half blocks or braille dots. In u32,
+    /// large scales take more cells than a terminal can have.
+    fn cells(&self, width: u16, height: u16, scale: u16) -> (u32, u32) {
+        let (width, height, scale) = (u32::from(width), u32::from(height), u32::from(scale));
+        match self {
+            RenderMode::Block => (width * scale * 2, height * scale),
+            RenderMode::HalfBlock => (width * scale, (height * scale).div_ceil(2)),
+            RenderMode::Braille => ((width * scale).div_ceil(2), (height * scale).div_ceil(4))
+        }
+    }
+}
+
+// A number of cells as a terminal coordinate, past the largest terminal
+// for the scales `resize` falls back from
+fn saturate(cells: u32) -> u16 {
+    cells.min(u32::from(u16::MAX)) as u16
+}
+
+// Bits of the dots of a braille cell, by row then column
+const BRAILLE_DOTS: [[u32; 2]; 4] = [[0x01, 0x08], [0x02, 0x10], [0x04, 0x20], [0x40, 0x80]];
+
+/// A terminal cell of the screen
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct Cell {
+    symbol: char,
+    foreground: Color,
+    background: Color
+}
+
+/// The screen drawn in a terminal, inside a border.
+///
+/// The cells last presented are kept, presenting a frame only writes the
+/// cells that changed since, so a sprite moving costs a few dozen bytes
+/// instead of a whole screen.
+pub struct Display<W: Write = Stdout> {
+    out: ByteCounter<W>,
+    // Mode and scale asked for, `fit` falls back from them
+    requested: (RenderMode, u16),
+    mode: RenderMode,
+    // Size of a pixel in the cells of the mode, see RenderMode::cells
+    scale: u16,
+    // Off colour first, Reset leaves the pixels that are off to the terminal background
+    palette: Palette,
+    // Off for terminals that only have 256 colours
+    truecolor: bool,
+    // Cells on the terminal, row by row, None when it has to be redrawn
+    shown: Option<Vec<Cell>>
+}
+
+impl Default for Display {
+    fn default() -> Self {
+        Self::new(1)
+    }
 }
 
 impl Display {
-    pub fn new() -> Self {
+    pub fn new(scale: u16) -> Self {
+        let mut display = Display::with_writer(stdout(), scale);
+        display.truecolor = palette::supports_truecolor();
+        display
+    }
+}
+
+impl<W: Write> Display<W> {
+    /// A display writing its escape codes to `out` instead of the terminal.
+    pub fn with_writer(out: W, scale: u16) -> Self {
         Display {
-            stdout: stdout()
+            out: ByteCounter { inner: out, bytes: 0 },
+            requested: (RenderMode::Block, scale),
+            mode: RenderMode::Block,
+            scale,
+            palette: Palette::DEFAULT,
+            truecolor: true,
+            shown: None
         }
     }
 
-    pub fn clear_screen(&mut self) {
-        self.stdout.execute(terminal::Clear(terminal::ClearType::All)).unwrap();
-    }
-
-    pub fn draw_pixel(&mut self, x: u16, y: u16, display: bool) {
-        let default_pixel = style::ResetColor;
-
-        match display {
-            true => {
-                self.stdout
-                .queue(cursor::MoveTo(x,y)).unwrap()
-                .queue( style::PrintStyledContent( "█".white())).unwrap();
-                self.stdout
-                .queue(cursor::MoveTo(x+1,y)).unwrap()
-                .queue( style::PrintStyledContent( "█".white())).unwrap();
-            },
-            false => {
-                self.stdout
-                .queue(cursor::MoveTo(x,y)).unwrap()
-                .queue(default_pixel).unwrap();
-                self.stdout
-                .queue(cursor::MoveTo(x+1,y)).unwrap()
-                .queue(default_pixel).unwrap();
+    /// Draws with `palette` from now on, its 24-bit colours replaced by
+    /// the closest of the 256 colour palette unless the terminal has them.
+    pub fn set_palette(&mut self, palette: Palette) {
+        self.palette = if self.truecolor { palette } else { palette.to_ansi_256() };
+        self.invalidate();
+    }
+
+    pub fn set_truecolor(&mut self, truecolor: bool) {
+        self.truecolor = truecolor;
+    }
+
+    pub fn mode(&self) -> RenderMode {
+        self.mode
+    }
+
+    pub fn scale(&self) -> u16 {
+        self.scale
+    }
+
+    /// Draws with `mode` from now on, at the scale given to `new`.
+    pub fn set_mode(&mut self, mode: RenderMode) {
+        self.requested.0 = mode;
+        self.mode = mode;
+        self.scale = self.requested.1;
+        self.invalidate();
+    }
+
+    /// Redraws everything on the next frame, when the terminal may have
+    /// been changed behind the display's back.
+    pub fn invalidate(&mut self) {
+        self.shown = None;
+    }
+
+    /// Prints cells in runs of the same symbol and colours, only setting
+    /// the colours when they change.
+    fn print_cells(&mut self, cells: &[Cell], colors: &mut Option<(Color, Color)>) {
+        for run in cells.chunk_by(|a, b| a == b) {
+            let cell = run[0];
+            if *colors != Some((cell.foreground, cell.background)) {
+                self.out
+                .queue(style::SetForegroundColor(cell.foreground)).unwrap()
+                .queue(style::SetBackgroundColor(cell.background)).unwrap();
+                *colors = Some((cell.foreground, cell.background));
             }
+            let text: String = std::iter::repeat_n(cell.symbol, run.len()).collect();
+            self.out.queue(style::Print(text)).unwrap();
+        }
+    }
+
+    /// Terminal columns and rows of the screen, without the border.
+    fn cells(&self, screen: &Framebuffer) -> (u16, u16) {
+        let (columns, rows) = self.mode.cells(screen.width(), screen.height(), self.scale);
+        (saturate(columns), saturate(rows))
+    }
+
+    /// The cells of a frame, row by row.
+    fn render(&self, screen: &Framebuffer) -> Vec<Cell> {
+        let (columns, rows) = self.cells(screen);
+        let scale = self.scale;
+        // In the units of the mode, scaled up pixels past the edge are off
+        let pixel = |x: u16, y: u16| {
+            let (x, y) = (x / scale, y / scale);
+            x < screen.width() && y < screen.height() && screen.get(x, y)
         };
+        // Blank cells keep the on colour too, runs of pixels then do not
+        // switch colours with every blank cell
+        let (foreground, background) = (self.palette.on(), self.palette.off());
+
+        let mut cells = Vec::with_capacity(usize::from(columns) * usize::from(rows));
+        for row in 0..rows {
+            for column in 0..columns {
+                let symbol = match self.mode {
+                    RenderMode::Block => if pixel(column / 2, row) { '█' } else { ' ' },
+                    RenderMode::HalfBlock => match (pixel(column, row * 2), pixel(column, row * 2 + 1)) {
+                        (true, true) => '█',
+                        (true, false) => '▀',
+                        (false, true) => '▄',
+                        (false, false) => ' '
+                    },
+                    RenderMode::Braille => {
+                        let mut dots = 0;
+                        for (dy, bits) in BRAILLE_DOTS.iter().enumerate() {
+                            for (dx, bit) in bits.iter().enumerate() {
+                                if pixel(column * 2 + dx as u16, row * 4 + dy as u16) {
+                                    dots |= bit;
+                                }
+                            }
+                        }
+                        if dots == 0 { ' ' } else { std::char::from_u32(0x2800 + dots).unwrap() }
+                    }
+                };
+                cells.push(Cell { symbol, foreground, background });
+            }
+        }
+        cells
+    }
+
+    fn draw_border(&mut self, columns: u16, rows: u16) {
+        let horizontal: String = std::iter::repeat_n('─', usize::from(columns)).collect();
+        self.out
+        .queue(style::ResetColor).unwrap()
+        .queue(cursor::MoveTo(0, 0)).unwrap()
+        .queue(style::Print(format!("┌{}┐", horizontal))).unwrap()
+        .queue(cursor::MoveTo(0, rows + 1)).unwrap()
+        .queue(style::Print(format!("└{}┘", horizontal))).unwrap();
+        for row in 1..=rows {
+            self.out
+            .queue(cursor::MoveTo(0, row)).unwrap()
+            .queue(style::Print('│')).unwrap()
+            .queue(cursor::MoveTo(columns + 1, row)).unwrap()
+            .queue(style::Print('│')).unwrap();
+        }
+    }
+
+    /// Terminal columns taken by the screen and its border.
+    pub fn width(&self) -> u16 {
+        saturate(self.mode.cells(WIDTH, HEIGHT, self.scale).0 + 2)
+    }
+
+    /// Terminal rows taken by the screen and its border.
+    pub fn height(&self) -> u16 {
+        saturate(self.mode.cells(WIDTH, HEIGHT, self.scale).1 + 2)
+    }
+
+    /// Prints `text` at a terminal position, clearing the rest of the line.
+    pub fn draw_text(&mut self, column: u16, row: u16, text: &str) {
+        self.out
+        .queue(style::ResetColor).unwrap()
+        .queue(cursor::MoveTo(column, row)).unwrap()
+        .queue(style::Print(text)).unwrap()
+        .queue(terminal::Clear(terminal::ClearType::UntilNewLine)).unwrap();
     }
 
     pub fn apply(&mut self) {
-        self.stdout.flush().unwrap();
+        self.out.flush().unwrap();
+    }
+
+    /// Bytes written to the terminal so far.
+    pub fn bytes_written(&self) -> u64 {
+        self.out.bytes
     }
 }
- 
-pub fn draw_screen(display: &mut Display, screen: &Vec<bool>) {
-    display.clear_screen();
-
-    // Draw the actual screen
-    for y in 0..HEIGHT {
-      for x in 0..WIDTH {
-        let pixel = screen[usize::from(x + y * WIDTH)];
-        display.draw_pixel((x * 2).into(), y.into(), pixel);
-      }
-    }
-
-    // Draw borders
-    for y in 0..HEIGHT {
-        for x in 0..WIDTH {
-          if (y == 0 || y == HEIGHT - 1) || (x == 0 || x == (WIDTH * 2) - 1) {
-            display.draw_pixel((x * 2).into(), y.into(), true);
-          }
+
+impl<W: Write> Renderer for Display<W> {
+    /// Switches to the alternate screen with the cursor hidden, `shutdown` switches back.
+    fn enter(&mut self) {
+        self.out
+        .queue(terminal::EnterAlternateScreen).unwrap()
+        .queue(cursor::Hide).unwrap()
+        .queue(terminal::Clear(terminal::ClearType::All)).unwrap();
+        self.invalidate();
+    }
+
+    /// Draws a frame, only writing the cells that changed since the last one.
+    fn present(&mut self, screen: &Framebuffer) {
+        let (columns, rows) = self.cells(screen);
+        let cells = self.render(screen);
+
+        let shown = match self.shown.take() {
+            Some(shown) if shown.len() == cells.len() => shown,
+            _ => {
+                self.draw_border(columns, rows);
+                // Nothing matches, every cell is drawn
+                vec![Cell { symbol: '\0', foreground: Color::Reset, background: Color::Reset }; cells.len()]
+            }
+        };
+
+        let mut colors = None;
+        for row in 0..rows {
+            let line_cells = usize::from(row) * usize::from(columns)..usize::from(row + 1) * usize::from(columns);
+            let (line, before) = (&cells[line_cells.clone()], &shown[line_cells]);
+            let changed = |column: usize| line[column] != before[column];
+
+            let mut column = 0;
+            while column < line.len() {
+                if !changed(column) {
+                    column += 1;
+                    continue;
+                }
+                let start = column;
+                let mut end = column + 1;
+                while end < line.len() && (end..(end + MIN_GAP).min(line.len())).any(changed) {
+                    end += 1;
+                }
+                // Inside the border
+                self.out.queue(cursor::MoveTo(start as u16 + 1, row + 1)).unwrap();
+                self.print_cells(&line[start..end], &mut colors);
+                column = end;
+            }
+        }
+        if colors.is_some() {
+            self.out.queue(style::ResetColor).unwrap();
+        }
+
+        self.shown = Some(cells);
+        self.apply();
+    }
+
+    /// Uses the mode and scale asked for when the screen fits in a terminal
+    /// of `size` columns and rows, with a status line below it. Otherwise
+    /// falls back to lower scales, then to denser modes. Clears the
+    /// terminal, to be called again when it is resized.
+    fn resize(&mut self, size: (u16, u16)) {
+        let fits = |mode: RenderMode, scale: u16| {
+            let (columns, rows) = mode.cells(WIDTH, HEIGHT, scale);
+            columns + 2 <= u32::from(size.0) && rows + 3 <= u32::from(size.1)
+        };
+
+        let (mut mode, requested_scale) = self.requested;
+        // Nothing fits, the densest mode is the least cut off
+        let mut scale = 1;
+        loop {
+            if let Some(fitting) = (1..=requested_scale).rev().find(|scale| fits(mode, *scale)) {
+                scale = fitting;
+                break;
+            }
+            match mode.denser() {
+                Some(denser) => mode = denser,
+                None => break
+            }
+        }
+
+        self.mode = mode;
+        self.scale = scale;
+        self.out.queue(terminal::Clear(terminal::ClearType::All)).unwrap();
+        self.invalidate();
+    }
+
+    /// Switches back from the alternate screen.
+    fn shutdown(&mut self) {
+        self.out
+        .queue(style::ResetColor).unwrap()
+        .queue(cursor::Show).unwrap()
+        .execute(terminal::LeaveAlternateScreen).unwrap();
+    }
+
+    fn show_status(&mut self, status: &str) {
+        self.draw_text(0, self.height(), status);
+        self.apply();
+    }
+
+    /// Draws the lines on the right of the screen and its border.
+    fn show_panel(&mut self, lines: &[String]) {
+        let column = self.width() + 2;
+        for (row, line) in lines.iter().enumerate() {
+            self.draw_text(column, row as u16, line);
         }
+        self.apply();
+    }
+}
+
+/// Counts the bytes going through to the terminal
+struct ByteCounter<W> {
+    inner: W,
+    bytes: u64
+}
+
+impl<W: Write> Write for ByteCounter<W> {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        let written = self.inner.write(buf)?;
+        self.bytes += written as u64;
+        Ok(written)
     }
 
-    display.apply();
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.inner.flush()
+    }
 }
 
-pub fn update_screen(start_x: u16, start_y: u16, bytes_to_read: u16, base_address: u16, memory: &Vec<u8>, screen: &mut Vec<bool>) -> bool{
+/// XORs a sprite onto the screen and returns true on collision.
+/// The sprite origin always wraps around the screen, pixels going past an
+/// edge are either clipped or wrapped to the other side.
+pub fn update_screen(start_x: u16, start_y: u16, bytes_to_read: u16, base_address: u16, memory: &[u8], screen: &mut Framebuffer, clip: bool) -> bool {
     let mut collision = false;
+    let (start_x, start_y) = (start_x % WIDTH, start_y % HEIGHT);
 
     // println!("setting pixel x {} - y {} - bytes_to_read: {} - base_address: {}", start_x, start_y, bytes_to_read, base_address);
     for idx in 0..bytes_to_read {
         let sprite = memory[usize::from(base_address + idx)];
         let y = start_y + idx;
+        if clip && y >= HEIGHT {
+            break;
+        }
+        let y = y % HEIGHT;
 
         // println!("sprite[{}][{}] - base_address {:b} {}", idx, base_address + idx, sprite, base_address);
         for sprite_idx in 0..8 {
-            let x = (start_x + sprite_idx);
-            let pixel_coordinate = x + (y * WIDTH);
+            let x = start_x + sprite_idx;
+            if clip && x >= WIDTH {
+                break;
+            }
+            let x = x % WIDTH;
 
             // println!("#bytes {} - {} - {}", idx, x, y);
             let bit = (sprite >> (7 - sprite_idx) & 0x01) == 1;
-            let existing_pixel = screen[usize::from(pixel_coordinate)];
-            if bit == true {
-                screen[usize::from(pixel_coordinate)] = !existing_pixel;
-                if existing_pixel == true {
-                    collision = true;
-                }
+            if bit && screen.toggle(x, y) {
+                collision = true;
             }
         }
     }
@@ -135,18 +475,128 @@ mod tests {
 
     #[test]
     fn display_graphic_test() {
-        let memory = SPRITES.clone();
+        let memory = SPRITES;
         let mut screen = screen_init();
 
         let (x, y) = (0, 0);
         let base_address = 0;
-        let collision = update_screen(x, y, 2, base_address, &memory.to_vec(), &mut screen);
+        let collision = update_screen(x, y, 2, base_address, memory, &mut screen, true);
 
         println!("screen values {:?}", screen);
-        assert_eq!(false, collision);
+        assert!(!collision);
+    }
+
+    #[test]
+    fn clip_and_wrap_sprite_test() {
+        // Sprite "0" drawn across the bottom right corner
+        let (x, y) = (WIDTH - 2, HEIGHT - 2);
+
+        let mut screen = screen_init();
+        update_screen(x, y, 5, 0, SPRITES, &mut screen, true);
+        assert_eq!(3, screen.count_on());
+
+        let mut screen = screen_init();
+        update_screen(x, y, 5, 0, SPRITES, &mut screen, false);
+        assert_eq!(14, screen.count_on());
+        // Last row of the sprite wrapped to the top left corner
+        assert!(screen.get(0, 2));
+    }
+
+    #[test]
+    fn present_only_changes_test() {
+        let mut screen = Framebuffer::new();
+        let mut display = Display::with_writer(Vec::new(), 1);
+        display.present(&screen);
+        let full = display.bytes_written();
+
+        // A sprite appearing in the middle of the screen
+        update_screen(30, 14, 5, 0, SPRITES, &mut screen, true);
+        display.present(&screen);
+        let diff = display.bytes_written() - full;
+        assert!(diff > 0 && diff * 20 < full, "{} bytes for a sprite, {} for the whole screen", diff, full);
+
+        // Nothing changed, nothing written
+        let before = display.bytes_written();
+        display.present(&screen);
+        assert_eq!(before, display.bytes_written());
+
+        display.invalidate();
+        display.present(&screen);
+        assert!(display.bytes_written() - before >= full);
+    }
+
+    #[test]
+    fn render_modes_test() {
+        let mut screen = Framebuffer::new();
+        // The top left pixel and the one below it, the one right of it
+        screen.set(0, 0, true);
+        screen.set(0, 1, true);
+        screen.set(1, 0, true);
+
+        let mut display = Display::with_writer(Vec::new(), 1);
+        display.set_mode(RenderMode::HalfBlock);
+        let cells = display.render(&screen);
+        assert_eq!((64, 16), display.cells(&screen));
+        assert_eq!(['█', '▀', ' '], [cells[0].symbol, cells[1].symbol, cells[2].symbol]);
+
+        let mut display = Display::with_writer(Vec::new(), 2);
+        display.set_mode(RenderMode::Braille);
+        let cells = display.render(&screen);
+        assert_eq!((64, 16), display.cells(&screen));
+        // At scale 2 the first cell is the first column of pixels, the
+        // second one is the top right pixel in the top two rows of dots
+        assert_eq!(['\u{28FF}', '\u{281B}', ' '], [cells[0].symbol, cells[1].symbol, cells[2].symbol]);
+    }
+
+    #[test]
+    fn palette_test() {
+        let mut screen = Framebuffer::new();
+        screen.set(0, 0, true);
+
+        let mut display = Display::with_writer(Vec::new(), 1);
+        display.set_palette(Palette::AMBER);
+        let cells = display.render(&screen);
+        assert_eq!((Palette::AMBER.on(), Palette::AMBER.off()), (cells[0].foreground, cells[0].background));
+
+        // Without 24-bit colours the closest of the 256 colours are used
+        display.set_truecolor(false);
+        display.set_palette(Palette::AMBER);
+        let cells = display.render(&screen);
+        assert_eq!(Color::AnsiValue(214), cells[0].foreground);
+    }
+
+    #[test]
+    fn fit_test() {
+        let mut display = Display::with_writer(Vec::new(), 2);
+
+        display.resize((300, 80));
+        assert_eq!((RenderMode::Block, 2), (display.mode(), display.scale()));
+        display.resize((130, 40));
+        assert_eq!((RenderMode::Block, 1), (display.mode(), display.scale()));
+        display.resize((80, 24));
+        assert_eq!((RenderMode::HalfBlock, 1), (display.mode(), display.scale()));
+        display.resize((40, 12));
+        assert_eq!((RenderMode::Braille, 1), (display.mode(), display.scale()));
+        // Grows back once the terminal is large enough again
+        display.resize((300, 80));
+        assert_eq!((RenderMode::Block, 2), (display.mode(), display.scale()));
+
+        // Too small for anything
+        display.set_mode(RenderMode::HalfBlock);
+        display.resize((10, 5));
+        assert_eq!((RenderMode::Braille, 1), (display.mode(), display.scale()));
+
+        // Scales too large for a terminal fall back like the others
+        let mut display = Display::with_writer(Vec::new(), 520);
+        display.resize((300, 80));
+        assert_eq!((RenderMode::Block, 2), (display.mode(), display.scale()));
+        // A screen of more than 65535 cells
+        display.resize((600, 200));
+        assert_eq!((RenderMode::Block, 4), (display.mode(), display.scale()));
+        display.present(&screen_init());
     }
 
-    fn screen_init() -> Vec<bool> {
-        vec![false; usize::from(WIDTH * HEIGHT)]
+    fn screen_init() -> Framebuffer {
+        Framebuffer::new()
     }
 }
\ No newline at end of file
@@ -1,66 +1,103 @@
-use crate::cpu::CPU;
+use crate::cpu::{KeyWait, CPU};
+use crate::error::Chip8Error;
 use crate::graphic;
-use crate::keyboard;
+use crate::quirks::MemoryIncrement;
 
-#[derive(Debug)]
-#[derive(PartialEq)]
+/// A decoded instruction with its operands, see `opcodes::decode`.
+///
+/// x and y are register numbers, kk a byte, addr a 12-bit address and n a nibble.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum Instruction {
-    CLS,
-    RET,
-    JP_ADDR,
-    CALL_ADDR,
-    SE_VX_BYTE,
-    SE_VX_VY,
-    LD_VX_BYTE,
-    ADD_VX_BYTE,
-    LD_VX_VY,
-    OR_VX_VY,
-    AND_VX_VY,
-    XOR_VX_VY,
-    ADD_VX_VY,
-    SUB_VX_VY,
-    SHR_VX_VY,
-    SUBN_VX_VY,
-    SHL_VX_VY,
-    SNE_VX_VY,
-    SNE_VX_BYTE,
-    LD_I_ADDR,
-    JP_V0_ADDR,
-    RND_VX_BYTE,
-    DRW,
-    SKP_VX,
-    SKNP_VX,
-    LD_VX_DT,
-    LD_VX_K,
-    LD_DT_VX,
-    LD_ST_VX,
-    LD_B_VX,
-    ADD_I_VX,
-    LD_F_VX,
-    LD_I_VX,
-    LD_VX_I
+    // 0nnn - SYS addr, machine code routine, ignored
+    Sys { addr: u16 },
+    // 00E0 - CLS
+    Cls,
+    // 00EE - RET
+    Ret,
+    // 1nnn - JP addr
+    Jp { addr: u16 },
+    // 2nnn - CALL addr
+    Call { addr: u16 },
+    // 3xkk - SE Vx, byte
+    SeByte { x: u8, kk: u8 },
+    // 4xkk - SNE Vx, byte
+    SneByte { x: u8, kk: u8 },
+    // 5xy0 - SE Vx, Vy
+    Se { x: u8, y: u8 },
+    // 6xkk - LD Vx, byte
+    LdByte { x: u8, kk: u8 },
+    // 7xkk - ADD Vx, byte
+    AddByte { x: u8, kk: u8 },
+    // 8xy0 - LD Vx, Vy
+    Ld { x: u8, y: u8 },
+    // 8xy1 - OR Vx, Vy
+    Or { x: u8, y: u8 },
+    // 8xy2 - AND Vx, Vy
+    And { x: u8, y: u8 },
+    // 8xy3 - XOR Vx, Vy
+    Xor { x: u8, y: u8 },
+    // 8xy4 - ADD Vx, Vy
+    Add { x: u8, y: u8 },
+    // 8xy5 - SUB Vx, Vy
+    Sub { x: u8, y: u8 },
+    // 8xy6 - SHR Vx {, Vy}
+    Shr { x: u8, y: u8 },
+    // 8xy7 - SUBN Vx, Vy
+    Subn { x: u8, y: u8 },
+    // 8xyE - SHL Vx {, Vy}
+    Shl { x: u8, y: u8 },
+    // 9xy0 - SNE Vx, Vy
+    Sne { x: u8, y: u8 },
+    // Annn - LD I, addr
+    LdI { addr: u16 },
+    // Bnnn - JP V0, addr
+    JpV0 { addr: u16 },
+    // Cxkk - RND Vx, byte
+    Rnd { x: u8, kk: u8 },
+    // Dxyn - DRW Vx, Vy, nibble
+    Drw { x: u8, y: u8, n: u8 },
+    // Ex9E - SKP Vx
+    Skp { x: u8 },
+    // ExA1 - SKNP Vx
+    Sknp { x: u8 },
+    // Fx07 - LD Vx, DT
+    LdVxDt { x: u8 },
+    // Fx0A - LD Vx, K
+    LdVxK { x: u8 },
+    // Fx15 - LD DT, Vx
+    LdDtVx { x: u8 },
+    // Fx18 - LD ST, Vx
+    LdStVx { x: u8 },
+    // Fx1E - ADD I, Vx
+    AddI { x: u8 },
+    // Fx29 - LD F, Vx
+    LdF { x: u8 },
+    // Fx33 - LD B, Vx
+    LdB { x: u8 },
+    // Fx55 - LD [I], Vx
+    LdIVx { x: u8 },
+    // Fx65 - LD Vx, [I]
+    LdVxI { x: u8 }
 }
 
 pub fn clear_screen(cpu: &mut CPU) -> bool {
     cpu.clear_screen();
-    return true;
+    true
 }
 
-pub fn ret(cpu: &mut CPU) {
-    cpu.pc = cpu.stack.pop_front().unwrap();
-    cpu.sp -= 1;
+pub fn ret(cpu: &mut CPU) -> Result<(), Chip8Error> {
+    cpu.pc = cpu.pop_stack()?;
+    Ok(())
 }
 
 pub fn jp_addr(cpu: &mut CPU, addr: u16) {
-    cpu.pc = addr;
-    cpu.pc -= 2;
+    cpu.pc = addr.wrapping_sub(2);
 }
 
-pub fn call_addr(cpu: &mut CPU, addr: u16) {
-    cpu.sp += 1;
-    cpu.stack.push_front(cpu.pc);
-    cpu.pc = addr;
-    cpu.pc -= 2;
+pub fn call_addr(cpu: &mut CPU, addr: u16) -> Result<(), Chip8Error> {
+    cpu.push_stack(cpu.pc)?;
+    cpu.pc = addr.wrapping_sub(2);
+    Ok(())
 }
 
 pub fn se_xv_byte(cpu: &mut CPU, x: u8, kk: u8)  {
@@ -94,28 +131,37 @@ pub fn se_vx_vy(cpu: &mut CPU, x: u8, y: u8)  {
 }
 
 pub fn ld_vx_byte(cpu: &mut CPU, x: u8, kk: u8) {
-    cpu.registers[usize::from(x)] = kk
+    cpu.set_register_value(x, kk);
 }
 
 pub fn add_vx_byte(cpu: &mut CPU, x: u8, kk: u8) {
-    let (result, _overflow) = cpu.registers[usize::from(x)].overflowing_add(kk);
-    cpu.registers[usize::from(x)] = result;
+    let (result, _overflow) = cpu.get_reg(x).overflowing_add(kk);
+    cpu.set_register_value(x, result);
 }
 
 pub fn ld_vx_vy(cpu: &mut CPU, x: u8, y: u8) {
-    cpu.registers[usize::from(x)] = cpu.get_reg(y);
+    cpu.set_register_value(x, cpu.get_reg(y));
 }
 
 pub fn or_vx_vy(cpu: &mut CPU, x: u8, y: u8) {
-    cpu.registers[usize::from(x)] = cpu.get_reg(x) | cpu.get_reg(y);
+    cpu.set_register_value(x, cpu.get_reg(x) | cpu.get_reg(y));
+    reset_vf(cpu);
 }
 
 pub fn and_vx_vy(cpu: &mut CPU, x: u8, y: u8) {
-    cpu.registers[usize::from(x)] = cpu.get_reg(x) & cpu.get_reg(y);
+    cpu.set_register_value(x, cpu.get_reg(x) & cpu.get_reg(y));
+    reset_vf(cpu);
 }
 
 pub fn xor_vx_vy(cpu: &mut CPU, x: u8, y: u8) {
-    cpu.registers[usize::from(x)] = cpu.get_reg(x) ^ cpu.get_reg(y);
+    cpu.set_register_value(x, cpu.get_reg(x) ^ cpu.get_reg(y));
+    reset_vf(cpu);
+}
+
+fn reset_vf(cpu: &mut CPU) {
+    if cpu.quirks.vf_reset {
+        cpu.set_reg_f(0);
+    }
 }
 
 pub fn add_vx_vy(cpu: &mut CPU, x: u8, y: u8) {
@@ -125,7 +171,7 @@ pub fn add_vx_vy(cpu: &mut CPU, x: u8, y: u8) {
     } else {
         cpu.set_reg_f(0);
     }
-    cpu.registers[usize::from(x)] = result;
+    cpu.set_register_value(x, result);
 }
 
 pub fn sub_vx_vy(cpu: &mut CPU, x: u8, y: u8) {
@@ -140,17 +186,18 @@ pub fn sub_vx_vy(cpu: &mut CPU, x: u8, y: u8) {
     cpu.set_register_value(x, result);
 }
 
-pub fn shr_vx_vy(cpu: &mut CPU, x: u8, y: u8) {
-    let quirks = true;
-
-    let reg_idx = if quirks {
-        x
+fn shift_source(cpu: &CPU, x: u8, y: u8) -> u8 {
+    if cpu.quirks.shift_vx {
+        cpu.get_reg(x)
     } else {
-        y
-    };
+        cpu.get_reg(y)
+    }
+}
 
-    cpu.set_reg_f(cpu.get_reg(reg_idx) & 0x01);
-    cpu.registers[usize::from(x)] >>= 1;
+pub fn shr_vx_vy(cpu: &mut CPU, x: u8, y: u8) {
+    let value = shift_source(cpu, x, y);
+    cpu.set_register_value(x, value >> 1);
+    cpu.set_reg_f(value & 0x01);
 }
 
 pub fn subn_vx_vy(cpu: &mut CPU, x: u8, y: u8) {
@@ -164,16 +211,9 @@ pub fn subn_vx_vy(cpu: &mut CPU, x: u8, y: u8) {
 }
 
 pub fn shl_vx_vy(cpu: &mut CPU, x: u8, y: u8) {
-    let quirks = true;
-
-    let reg_idx = if quirks {
-        x
-    } else {
-        y
-    };
-
-    cpu.set_reg_f((cpu.get_reg(reg_idx) >> 7) & 0x01);
-    cpu.registers[usize::from(reg_idx)] <<= 1;
+    let value = shift_source(cpu, x, y);
+    cpu.set_register_value(x, value << 1);
+    cpu.set_reg_f((value >> 7) & 0x01);
 }
 
 pub fn sne_vx_vy(cpu: &mut CPU, x: u8, y: u8) {
@@ -185,103 +225,149 @@ pub fn sne_vx_vy(cpu: &mut CPU, x: u8, y: u8) {
 }
 
 pub fn ld_i_addr(cpu: &mut CPU, addr: u16) {
-    cpu.i = addr;
+    cpu.set_i(addr);
 }
 
 pub fn jp_v0_addr(cpu: &mut CPU, addr: u16) {
-    cpu.pc = (cpu.registers[0] as u16) + addr;
-    cpu.pc -= 2;
+    let reg_idx = if cpu.quirks.jump_vx {
+        ((addr & 0x0F00) >> 8) as u8
+    } else {
+        0
+    };
+
+    cpu.pc = ((cpu.get_reg(reg_idx) as u16) + addr).wrapping_sub(2);
 }
 
 pub fn random_vx_byte(cpu: &mut CPU, x: u8, kk: u8) {
-    use rand::prelude::*;
-    cpu.registers[usize::from(x)] = random::<u8>() & kk;
+    let value = cpu.rng.next_byte();
+    cpu.set_register_value(x, value & kk);
 }
 
-pub fn draw(cpu: &mut CPU, x: u8, y: u8, nibble: u8) -> bool {
+pub fn draw(cpu: &mut CPU, x: u8, y: u8, nibble: u8) -> Result<bool, Chip8Error> {
+    let sprite_end = usize::from(cpu.i) + usize::from(nibble);
+    if sprite_end > cpu.memory.len() {
+        return Err(Chip8Error::MemoryOutOfBounds { addr: sprite_end - 1 });
+    }
+
+    if cpu.quirks.display_wait {
+        if !cpu.vblank {
+            // Execute this instruction again until the next timer tick
+            cpu.pc -= 2;
+            return Ok(false);
+        }
+        cpu.vblank = false;
+    }
+
+    // Sprite rows are read through the CPU for read watchpoints
+    for addr in usize::from(cpu.i)..sprite_end {
+        cpu.load_memory(addr)?;
+    }
+
     let start_x = cpu.get_reg(x);
     let start_y = cpu.get_reg(y);
-    let collision = graphic::update_screen(start_x.into(), start_y.into(), nibble.into(), cpu.i, &cpu.memory, &mut cpu.screen);
+    let collision = graphic::update_screen(start_x.into(), start_y.into(), nibble.into(), cpu.i, &cpu.memory, &mut cpu.screen, cpu.quirks.clip_sprites);
     cpu.set_reg_f(if collision {
         1
     } else {
         0
     });
-    return true;
+    Ok(true)
+}
+
+// Only the low nibble of Vx selects the key, like on the VIP
+fn is_key_held(cpu: &CPU, x: u8) -> bool {
+    cpu.keys & (1 << (cpu.get_reg(x) & 0xF)) != 0
 }
 
 pub fn skip_vx(cpu: &mut CPU, x: u8) {
-    match cpu.key_pressed {
-        Some(key) => {
-            if key == cpu.get_reg(x).into() {
-                cpu.pc += 2;
-            }
-        }
-        _ => {}
+    if is_key_held(cpu, x) {
+        cpu.pc += 2;
     }
 }
 
 pub fn skipn_vx(cpu: &mut CPU, x: u8) {
-    let expected_key = cpu.get_reg(x);
-    match cpu.key_pressed {
-        Some(key) => {
-            if key != expected_key.into() {
-                cpu.pc += 2;
-            }
-        }
-        _ => cpu.pc += 2
+    if !is_key_held(cpu, x) {
+        cpu.pc += 2;
     }
 }
 
-use std::convert::TryInto;
 pub fn load_vx_k(cpu: &mut CPU, x: u8) {
-    keyboard::wait_for_key_events(cpu);
-    cpu.set_register_value(x, cpu.key_pressed.unwrap().try_into().unwrap());
-    cpu.key_pressed = None;
+    // The CPU stalls in CPU::poll_key_wait until a key is read
+    cpu.key_wait = Some(KeyWait {
+        x,
+        // A key held when waiting on release counts as pressed, its release completes the wait.
+        // Waiting on press needs a new press, or a loop of LD Vx, K would read the same one again.
+        held: if cpu.quirks.key_on_press { cpu.keys } else { 0 },
+        pressed: None
+    });
+}
+
+/// Fails on the first byte of `len` bytes from I outside of memory, so
+/// instructions touching several bytes fault before changing any of them.
+fn check_i_range(cpu: &CPU, len: usize) -> Result<(), Chip8Error> {
+    let i = usize::from(cpu.i);
+    if i + len > cpu.memory.len() {
+        return Err(Chip8Error::MemoryOutOfBounds { addr: i.max(cpu.memory.len()) });
+    }
+    Ok(())
 }
 
-pub fn load_bytes_vx(cpu: &mut CPU, x: u8) {
+pub fn load_bytes_vx(cpu: &mut CPU, x: u8) -> Result<(), Chip8Error> {
+    check_i_range(cpu, 3)?;
     let reg_value = cpu.get_reg(x);
-    cpu.update_memory(cpu.i, reg_value  / 100);
-    cpu.update_memory(cpu.i + 1, reg_value % 100 / 10);
-    cpu.update_memory(cpu.i + 2, reg_value % 10);
+    let i = usize::from(cpu.i);
+    cpu.update_memory(i, reg_value  / 100)?;
+    cpu.update_memory(i + 1, reg_value % 100 / 10)?;
+    cpu.update_memory(i + 2, reg_value % 10)
 }
 
-pub fn load_vx_i(cpu: &mut CPU, x: u8) {
+pub fn load_vx_i(cpu: &mut CPU, x: u8) -> Result<(), Chip8Error> {
+    check_i_range(cpu, usize::from(x) + 1)?;
     for idx in 0..(x + 1) {
         // println!("Getting Memory idx {} value {}", cpu.i + idx as u16, cpu.memory[usize::from(cpu.i + idx as u16)]);
-        cpu.set_register_value(idx, cpu.memory[usize::from(cpu.i + idx as u16)].into());
+        let value = cpu.load_memory(usize::from(cpu.i) + usize::from(idx))?;
+        cpu.set_register_value(idx, value);
     }
-    // Quirks
-    cpu.i += (x + 1) as u16;
+    increment_i(cpu, x);
+    Ok(())
 }
 
-pub fn load_i_vx(cpu: &mut CPU, x: u8) {
+pub fn load_i_vx(cpu: &mut CPU, x: u8) -> Result<(), Chip8Error> {
+    check_i_range(cpu, usize::from(x) + 1)?;
     let reg_idx = x;
     for idx in 0..(reg_idx + 1) {
-        cpu.update_memory(cpu.i + idx as u16, cpu.get_reg(idx) as u8);
+        cpu.update_memory(usize::from(cpu.i) + usize::from(idx), cpu.get_reg(idx))?;
+    }
+    increment_i(cpu, x);
+    Ok(())
+}
+
+fn increment_i(cpu: &mut CPU, x: u8) {
+    match cpu.quirks.memory_increment {
+        MemoryIncrement::XPlusOne => cpu.set_i(cpu.i + (x + 1) as u16),
+        MemoryIncrement::X => cpu.set_i(cpu.i + x as u16),
+        MemoryIncrement::None => {}
     }
-    // Quirks
-    cpu.i += (x + 1) as u16;
 }
 
 pub fn load_f_vx(cpu: &mut CPU, x: u8) {
-    let reg_value = cpu.get_reg(x);
-    cpu.i = (reg_value * 5).into();
+    // Font sprites are 5 bytes long, only the lowest digit of Vx is used
+    let reg_value = cpu.get_reg(x) & 0x0F;
+    cpu.set_i(u16::from(reg_value) * 5);
 }
 
 pub fn add_i_vx(cpu: &mut CPU, x: u8) {
-    cpu.i = cpu.i + cpu.registers[usize::from(x)] as u16;
+    cpu.set_i(cpu.i.wrapping_add(cpu.get_reg(x) as u16));
 }
 
 pub fn load_st_vx(cpu: &mut CPU, x: u8) {
-    cpu.st = cpu.get_reg(x).into();
+    cpu.st = cpu.get_reg(x);
 }
 
 pub fn load_dt_vx(cpu: &mut CPU, x: u8) {
-    cpu.dt = cpu.get_reg(x).into();
+    cpu.dt = cpu.get_reg(x);
 }
 
 pub fn load_vx_dt(cpu: &mut CPU, x: u8) {
-    cpu.set_register_value(x, cpu.dt.try_into().unwrap());
+    cpu.set_register_value(x, cpu.dt);
 }
\ No newline at end of file
@@ -1,60 +1,231 @@
-use crate::cpu::CPU;
-use crossterm::event::poll;
+use crate::chip8::Chip8;
+use crate::keymap::Keymap;
+use crossterm::event::{poll, KeyEventKind, KeyboardEnhancementFlags, PopKeyboardEnhancementFlags, PushKeyboardEnhancementFlags};
+use crossterm::{execute, terminal};
 
 use crossterm::{event::read, event::Event, event::KeyEvent, event::KeyCode};
-fn update_keyboard(cpu: &mut CPU, key_pressed: char) {
-  match key_pressed {
-    '0' => cpu.key_pressed = Some(0x0),
-    '1' => cpu.key_pressed = Some(0x1),
-    '2' => cpu.key_pressed = Some(0x2),
-    '3' => cpu.key_pressed = Some(0x3),
-    '4' => cpu.key_pressed = Some(0x4),
-    '5' => cpu.key_pressed = Some(0x5),
-    '6' => cpu.key_pressed = Some(0x6),
-    '7' => cpu.key_pressed = Some(0x7),
-    '8' => cpu.key_pressed = Some(0x8),
-    '9' => cpu.key_pressed = Some(0x9),
-    'a' => cpu.key_pressed = Some(0xA),
-    'b' => cpu.key_pressed = Some(0xB),
-    'c' => cpu.key_pressed = Some(0xC),
-    'd' => cpu.key_pressed = Some(0xD),
-    'e' => cpu.key_pressed = Some(0xE),
-    'f' => cpu.key_pressed = Some(0xF),
-    _ => {
-      cpu.key_pressed = None
-    }
-  }
+use std::io::stdout;
+use std::time::{Duration, Instant};
+
+/// How long a key counts as held after the terminal last sent it, when
+/// the terminal does not report key releases
+pub const DEFAULT_AUTO_RELEASE: Duration = Duration::from_millis(150);
+
+/// How long a key counts as held after it was pressed, until the terminal
+/// repeats it. Terminals wait longer before the first repeat than between
+/// repeats, 500ms by default for most of them.
+pub const FIRST_REPEAT_DELAY: Duration = Duration::from_millis(600);
+
+/// Emulator commands triggered from the keyboard, outside of the CHIP-8 keypad
+#[derive(Debug, PartialEq)]
+pub enum Action {
+    Quit,
+    TogglePause,
+    // Debugger commands, only used with --debug
+    Step,
+    StepOver,
+    Continue,
+    Prompt,
+    SaveState,
+    LoadState,
+    // Sent again by key repeat while the key is held
+    Rewind,
+    Screenshot,
+    ToggleRecording,
+    // The terminal was resized, the display has to fit it again
+    Resize
 }
 
-use std::time::Duration;
-pub fn update_events(cpu: &mut CPU) -> bool {
-    let event_available = poll(Duration::from_millis(0));
-    if let Ok(true) = event_available {
-      let event = read().unwrap();
-      match event  {
-        Event::Key(KeyEvent { code, .. }) => {
-          match code {
-            KeyCode::Esc => {
-              return false;
-            },
-            KeyCode::Char(c) => {
-              update_keyboard(cpu, c);
+/// Feeds the terminal keyboard to the CHIP-8 keypad through a keymap.
+/// Keys bound in the keymap go to the keypad even when they also trigger
+/// an emulator command.
+///
+/// Terminals supporting the kitty keyboard protocol report key releases,
+/// keys are held from their press to their release. Other terminals only
+/// repeat a key while it is held, so a key is released once it has not
+/// been repeated for the auto-release timeout, or for `FIRST_REPEAT_DELAY`
+/// before its first repeat.
+pub struct Keyboard {
+    keymap: Keymap,
+    auto_release: Duration,
+    // Set when the terminal reports releases
+    release_events: bool,
+    // When each held key was last pressed or repeated
+    held_since: [Option<Instant>; 16],
+    // Whether each held key was repeated since it was pressed
+    repeated: [bool; 16]
+}
+
+impl Keyboard {
+    pub fn new(keymap: Keymap, auto_release: Duration) -> Self {
+        Keyboard {
+            keymap,
+            auto_release,
+            release_events: false,
+            held_since: [None; 16],
+            repeated: [false; 16]
+        }
+    }
+
+    /// Asks the terminal to report key releases, once raw mode is enabled.
+    /// Returns false when it cannot, the auto-release timeout is used then.
+    pub fn enable_release_events(&mut self) -> bool {
+        if !terminal::supports_keyboard_enhancement().unwrap_or(false) {
+            return false;
+        }
+        let flags = KeyboardEnhancementFlags::DISAMBIGUATE_ESCAPE_CODES | KeyboardEnhancementFlags::REPORT_EVENT_TYPES;
+        self.release_events = execute!(stdout(), PushKeyboardEnhancementFlags(flags)).is_ok();
+        self.release_events
+    }
+
+    /// Puts the terminal back the way `enable_release_events` found it.
+    pub fn disable_release_events(&mut self) {
+        if self.release_events {
+            let _ = execute!(stdout(), PopKeyboardEnhancementFlags);
+            self.release_events = false;
+        }
+    }
+
+    /// Handles the pending keyboard events, updating the keypad of `chip8`.
+    /// Stops at the first emulator command, the events after it are
+    /// handled on the next call.
+    pub fn update_events(&mut self, chip8: &mut Chip8) -> Option<Action> {
+        let action = self.read_events(chip8);
+        if !self.release_events {
+            self.release_expired(chip8, Instant::now());
+        }
+        action
+    }
+
+    /// Releases the keys the terminal stopped repeating by `now`.
+    fn release_expired(&mut self, chip8: &mut Chip8, now: Instant) {
+        for key in 0..16 {
+            let timeout = if self.repeated[usize::from(key)] {
+                self.auto_release
+            } else {
+                self.auto_release.max(FIRST_REPEAT_DELAY)
+            };
+            if self.held_since[usize::from(key)].is_some_and(|since| now - since >= timeout) {
+                self.set_key(chip8, key, false, now);
+            }
+        }
+    }
+
+    fn read_events(&mut self, chip8: &mut Chip8) -> Option<Action> {
+        while let Ok(true) = poll(Duration::from_millis(0)) {
+          let (code, kind) = match read() {
+            Ok(Event::Key(KeyEvent { code, kind, .. })) => (code, kind),
+            Ok(Event::Resize(..)) => return Some(Action::Resize),
+            _ => continue
+          };
+          if let KeyCode::Char(c) = code {
+            if let Some(key) = self.keymap.key(c) {
+              self.set_key(chip8, key, kind != KeyEventKind::Release, Instant::now());
+              continue;
             }
+          }
+          if kind == KeyEventKind::Release {
+            continue;
+          }
+
+          match code {
+            KeyCode::Esc => return Some(Action::Quit),
+            KeyCode::Char('p') => return Some(Action::TogglePause),
+            KeyCode::Backspace => return Some(Action::Rewind),
+            KeyCode::F(2) => return Some(Action::Screenshot),
+            KeyCode::F(3) => return Some(Action::ToggleRecording),
+            KeyCode::F(5) => return Some(Action::SaveState),
+            KeyCode::F(9) => return Some(Action::LoadState),
+            KeyCode::F(11) => return Some(Action::Step),
+            KeyCode::F(10) => return Some(Action::StepOver),
+            KeyCode::Char('g') => return Some(Action::Continue),
+            KeyCode::Char(':') => return Some(Action::Prompt),
             _ => {}
           }
         }
+        None
+    }
+
+    fn set_key(&mut self, chip8: &mut Chip8, key: u8, pressed: bool, now: Instant) {
+        let idx = usize::from(key);
+        // Pressed again while held, the terminal repeated it
+        self.repeated[idx] = pressed && self.held_since[idx].is_some();
+        self.held_since[idx] = if pressed { Some(now) } else { None };
+        chip8.set_key(key, pressed);
+    }
+}
+
+/// Reads the emulator commands available without a keypad, for replays:
+/// quit and pause, and terminal resizes.
+pub fn poll_action() -> Option<Action> {
+    if let Ok(true) = poll(Duration::from_millis(0)) {
+      match read() {
+        Ok(Event::Key(KeyEvent { code, kind, .. })) => match code {
+          _ if kind == KeyEventKind::Release => {}
+          KeyCode::Esc => return Some(Action::Quit),
+          KeyCode::Char('p') => return Some(Action::TogglePause),
+          _ => {}
+        },
+        Ok(Event::Resize(..)) => return Some(Action::Resize),
         _ => {}
-      };
+      }
     }
-    return true;
+    None
 }
 
-pub fn wait_for_key_events(cpu: &mut CPU) {
+/// Reads a line typed on the keyboard, blocking until Enter or Esc.
+/// `echo` is called with the text typed so far after every key.
+/// Returns None when cancelled with Esc.
+pub fn read_line(mut echo: impl FnMut(&str)) -> Option<String> {
+    let mut line = String::new();
+    echo(&line);
     loop {
-        update_events(cpu);
-        if let Some(_k) = cpu.key_pressed {
-            cpu.key_pressed;
-            break;
+      if let Ok(Event::Key(KeyEvent { code, kind, .. })) = read() {
+        if kind == KeyEventKind::Release {
+          continue;
+        }
+        match code {
+          KeyCode::Enter => return Some(line),
+          KeyCode::Esc => return None,
+          KeyCode::Backspace => {
+            line.pop();
+          },
+          KeyCode::Char(c) => line.push(c),
+          _ => {}
         }
+        echo(&line);
+      }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn auto_release_test() {
+        let mut keyboard = Keyboard::new(Keymap::default(), DEFAULT_AUTO_RELEASE);
+        let mut chip8 = Chip8::new();
+        let start = Instant::now();
+        let at = |ms: u64| start + Duration::from_millis(ms);
+
+        // Held across the gap before the first repeat
+        keyboard.set_key(&mut chip8, 5, true, start);
+        keyboard.release_expired(&mut chip8, at(450));
+        assert_eq!(1 << 5, chip8.pressed_keys());
+
+        // Repeats come faster, the shorter timeout applies
+        keyboard.set_key(&mut chip8, 5, true, at(500));
+        keyboard.release_expired(&mut chip8, at(600));
+        assert_eq!(1 << 5, chip8.pressed_keys());
+        keyboard.release_expired(&mut chip8, at(650));
+        assert_eq!(0, chip8.pressed_keys());
+
+        // A tap without repeats is released after the first repeat delay
+        keyboard.set_key(&mut chip8, 5, true, at(1000));
+        keyboard.release_expired(&mut chip8, at(1599));
+        assert_eq!(1 << 5, chip8.pressed_keys());
+        keyboard.release_expired(&mut chip8, at(1600));
+        assert_eq!(0, chip8.pressed_keys());
     }
-}
\ No newline at end of file
+}
@@ -0,0 +1,150 @@
+use std::fmt;
+
+pub const PRESET_NAMES: &[&str] = &["qwerty", "azerty", "hex"];
+
+// Keypad keys as laid out on the COSMAC VIP, the order of keymap files
+const LAYOUT: [u8; 16] = [
+    0x1, 0x2, 0x3, 0xC,
+    0x4, 0x5, 0x6, 0xD,
+    0x7, 0x8, 0x9, 0xE,
+    0xA, 0x0, 0xB, 0xF
+];
+
+/// Terminal keys bound to the 16 keys of the CHIP-8 keypad.
+///
+/// Keymaps are written as the 4x4 keypad, the characters bound to
+///
+/// ```text
+/// 1 2 3 C
+/// 4 5 6 D
+/// 7 8 9 E
+/// A 0 B F
+/// ```
+///
+/// separated by whitespace, or as the name of a preset.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Keymap {
+    // Character bound to each keypad key, lowercase
+    keys: [char; 16]
+}
+
+impl Keymap {
+    /// The left of a QWERTY keyboard: 1234, QWER, ASDF and ZXCV
+    pub const QWERTY: Keymap = Keymap::from_layout(['1', '2', '3', '4', 'q', 'w', 'e', 'r', 'a', 's', 'd', 'f', 'z', 'x', 'c', 'v']);
+
+    /// The same keys on an AZERTY keyboard, the top row without shift
+    pub const AZERTY: Keymap = Keymap::from_layout(['&', 'é', '"', '\'', 'a', 'z', 'e', 'r', 'q', 's', 'd', 'f', 'w', 'x', 'c', 'v']);
+
+    /// Each keypad key bound to its hex digit
+    pub const HEX: Keymap = Keymap {
+        keys: ['0', '1', '2', '3', '4', '5', '6', '7', '8', '9', 'a', 'b', 'c', 'd', 'e', 'f']
+    };
+
+    const fn from_layout(chars: [char; 16]) -> Keymap {
+        let mut keys = [' '; 16];
+        let mut idx = 0;
+        while idx < 16 {
+            keys[LAYOUT[idx] as usize] = chars[idx];
+            idx += 1;
+        }
+        Keymap { keys }
+    }
+
+    /// Looks up a preset from one of `PRESET_NAMES`.
+    pub fn from_name(name: &str) -> Option<Keymap> {
+        match name.trim().to_lowercase().as_str() {
+            "qwerty" => Some(Keymap::QWERTY),
+            "azerty" => Some(Keymap::AZERTY),
+            "hex" => Some(Keymap::HEX),
+            _ => None
+        }
+    }
+
+    /// The name of the preset this keymap is, if any.
+    pub fn name(&self) -> Option<&'static str> {
+        PRESET_NAMES.iter().copied().find(|name| Keymap::from_name(name) == Some(*self))
+    }
+
+    /// Reads a preset name or a keymap written as the keypad.
+    pub fn parse(text: &str) -> Result<Keymap, String> {
+        if let Some(keymap) = Keymap::from_name(text) {
+            return Ok(keymap);
+        }
+
+        let mut chars = [' '; 16];
+        let mut count = 0;
+        for word in text.split_whitespace() {
+            let mut word_chars = word.chars().flat_map(char::to_lowercase);
+            let c = match (word_chars.next(), word_chars.next()) {
+                (Some(c), None) => c,
+                _ => return Err(format!("expected a single character, got '{}'", word))
+            };
+            if chars[..count.min(16)].contains(&c) {
+                return Err(format!("'{}' is bound twice", c));
+            }
+            if count < 16 {
+                chars[count] = c;
+            }
+            count += 1;
+        }
+        if count != 16 {
+            return Err(format!("expected a preset ({}) or 16 keys laid out as the keypad, got {} keys", PRESET_NAMES.join(", "), count));
+        }
+        Ok(Keymap::from_layout(chars))
+    }
+
+    /// The keypad key bound to `c`, if any.
+    pub fn key(&self, c: char) -> Option<u8> {
+        let c = c.to_lowercase().next().unwrap_or(c);
+        self.keys.iter().position(|bound| *bound == c).map(|key| key as u8)
+    }
+}
+
+impl Default for Keymap {
+    fn default() -> Self {
+        Keymap::QWERTY
+    }
+}
+
+/// Writes the keymap as the keypad, in the format `parse` reads.
+impl fmt::Display for Keymap {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for (row, keys) in LAYOUT.chunks(4).enumerate() {
+            if row > 0 {
+                writeln!(f)?;
+            }
+            let chars: Vec<String> = keys.iter().map(|key| self.keys[usize::from(*key)].to_string()).collect();
+            write!(f, "{}", chars.join(" "))?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn presets_test() {
+        for name in PRESET_NAMES {
+            assert!(Keymap::from_name(name).is_some());
+        }
+        assert_eq!(Some(0xC), Keymap::QWERTY.key('4'));
+        assert_eq!(Some(0x0), Keymap::QWERTY.key('X'));
+        assert_eq!(Some(0x4), Keymap::AZERTY.key('a'));
+        assert_eq!(Some(0xA), Keymap::HEX.key('a'));
+        assert_eq!(None, Keymap::QWERTY.key('p'));
+    }
+
+    #[test]
+    fn parse_test() {
+        assert_eq!(Ok(Keymap::QWERTY), Keymap::parse(&Keymap::QWERTY.to_string()));
+        assert_eq!(Ok(Keymap::AZERTY), Keymap::parse(" Azerty\n"));
+        assert_eq!(Ok(Keymap::HEX), Keymap::parse("1 2 3 c\n4 5 6 d\n7 8 9 e\na 0 b f"));
+
+        assert!(Keymap::parse("1 2 3 4").is_err());
+        assert!(Keymap::parse("1 2 3 4 q w e r a s d f z x c c").is_err());
+        assert!(Keymap::parse("1 2 3 4 q w e r a s d f z x c vv").is_err());
+        assert!(Keymap::parse("1 2 3 4 q w e r a s d f z x c v b").is_err());
+    }
+}
@@ -0,0 +1,28 @@
+pub mod assembler;
+pub mod cache;
+pub mod capture;
+pub mod chip8;
+pub mod config;
+pub mod cpu;
+pub mod debugger;
+pub mod disassembler;
+pub mod error;
+pub mod framebuffer;
+pub mod opcodes;
+pub mod palette;
+pub mod graphic;
+pub mod instructions;
+pub mod keyboard;
+pub mod keymap;
+pub mod movie;
+pub mod quirks;
+pub mod renderer;
+pub mod rewind;
+pub mod rng;
+pub mod savestate;
+pub mod scheduler;
+pub mod watchpoints;
+
+pub use chip8::Chip8;
+pub use error::Chip8Error;
+pub use framebuffer::Framebuffer;
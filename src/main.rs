@@ -1,59 +1,641 @@
-use crossterm::terminal::enable_raw_mode;
-use std::fs;
-use std::time::Duration;
-use std::{thread, time};
-
-pub mod cpu;
-pub mod opcodes;
-pub mod graphic;
-pub mod instructions;
-pub mod keyboard;
-
-fn load_game(rom_path: &str) -> Vec<u8> {
-    println!("Loading rom - {}", rom_path);
-    let rom_content = fs::read(rom_path);
-  
-    match rom_content {
-      Ok(game) => game,
-      Err(msg) => {
-          println!("Could not load rom {}", msg);
-          panic!();
-      }
+use crossterm::terminal::{self, disable_raw_mode, enable_raw_mode};
+use std::convert::TryFrom;
+use std::ffi::OsStr;
+use std::fs::File;
+use std::io::{BufWriter, Read, Write};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+use std::{env, fs, io, process, thread};
+
+use chip8r::capture::{self, GifRecorder, ImageFormat};
+use chip8r::debugger::{self, Debugger};
+use chip8r::keyboard::{Action, Keyboard};
+use chip8r::config::{Config, RomFiles, Settings, Sources};
+use chip8r::graphic::RenderMode;
+use chip8r::movie::{Movie, Replay};
+use chip8r::palette::Palette;
+use chip8r::renderer::Renderer;
+use chip8r::rewind::Rewind;
+use chip8r::scheduler::Scheduler;
+use chip8r::{assembler, disassembler, graphic, keyboard, savestate, Chip8, Chip8Error, Framebuffer};
+
+mod cli;
+
+use cli::{Command, RomSource, RunOptions};
+
+const ROMS_DIR: &str = "roms";
+// Save state file for roms read from stdin
+const STATE_FILE: &str = "chip8r.state";
+// Screenshots and recordings of roms read from stdin are named after it
+const CAPTURE_STEM: &str = "chip8r";
+
+// Longest stretch of time emulated at once, so the emulator does not try
+// to catch up after the process was suspended
+const MAX_CATCH_UP: Duration = Duration::from_millis(250);
+
+// Terminals do not report key releases, the rewind key counts as held as
+// long as key repeat keeps sending it. The first repeat comes later.
+const REWIND_FIRST_REPEAT: Duration = keyboard::FIRST_REPEAT_DELAY;
+const REWIND_REPEAT: Duration = Duration::from_millis(150);
+
+fn load_game(rom: &RomSource) -> Result<Vec<u8>, String> {
+    let game = match rom {
+        RomSource::File(rom_path) => {
+            fs::read(rom_path).map_err(|msg| format!("could not load rom {} - {}", rom_path, msg))?
+        }
+        RomSource::Stdin => {
+            let mut game = Vec::new();
+            io::stdin().read_to_end(&mut game).map_err(|msg| format!("could not read rom from stdin - {}", msg))?;
+            game
+        }
+    };
+    Ok(game)
+}
+
+/// Settings from the config file and the `.quirks` and `.keymap` files
+/// next to the rom, with where each came from.
+fn rom_settings(config: Option<&str>, rom: &RomSource, game: &[u8]) -> Result<(Settings, Sources), String> {
+    let config = load_config(config)?;
+    let files = match rom {
+        RomSource::File(rom_path) => RomFiles::load(Path::new(rom_path))?,
+        RomSource::Stdin => RomFiles::default()
+    };
+    Ok(config.rom_settings(Some(&savestate::rom_hash(game)), &files))
+}
+
+/// The config file given on the command line, which must exist, or the
+/// one at the default path if there is one.
+fn load_config(path: Option<&str>) -> Result<Config, String> {
+    match path {
+        Some(path) if !Path::new(path).is_file() => Err(format!("could not read {} - no such file", path)),
+        Some(path) => Config::load(Path::new(path)),
+        None => match Config::default_path() {
+            Some(path) => Config::load(&path),
+            None => Ok(Config::default())
+        }
     }
 }
 
-fn main() {
-    enable_raw_mode().unwrap();
+/// The command line options over the settings.
+fn apply_options(settings: &mut Settings, options: &RunOptions) -> Result<(), String> {
+    if let Some(ipf) = options.ipf {
+        settings.ipf = ipf;
+        settings.cpu_hz = None;
+    }
+    if options.cpu_hz.is_some() {
+        settings.cpu_hz = options.cpu_hz;
+    }
+    settings.hz = options.hz.unwrap_or(settings.hz);
+    settings.scale = options.scale.unwrap_or(settings.scale);
+    settings.renderer = options.renderer.unwrap_or(settings.renderer);
+    settings.palette = options.theme.unwrap_or(settings.palette);
+    settings.palette.colors[0] = options.background.unwrap_or(settings.palette.off());
+    settings.palette.colors[1] = options.foreground.unwrap_or(settings.palette.on());
+    settings.quirks = options.quirks.unwrap_or(settings.quirks);
+    if let Some(keymap) = &options.keymap {
+        settings.keymap = cli::parse_keymap(keymap)?;
+    }
+    Ok(())
+}
+
+fn show_config(rom: Option<RomSource>, config: Option<String>) -> Result<(), String> {
+    let (settings, sources) = match &rom {
+        Some(rom) => rom_settings(config.as_deref(), rom, &load_game(rom)?)?,
+        None => load_config(config.as_deref())?.rom_settings(None, &RomFiles::default())
+    };
+    print!("{}", settings.show(&sources));
+    Ok(())
+}
+
+fn disasm(rom: &RomSource, source: bool) -> Result<(), String> {
+    let game = load_game(rom)?;
+    let disassembly = disassembler::disassemble(&game);
+    if source {
+        print!("{}", disassembly.source());
+    } else {
+        print!("{}", disassembly.listing());
+    }
+    Ok(())
+}
+
+fn asm(source: &str, output: Option<String>, symbols: Option<String>) -> Result<(), String> {
+    let source = Path::new(source);
+    let assembly = assembler::assemble_file(source).map_err(|errors| {
+        let errors: Vec<String> = errors.iter().map(|error| error.to_string()).collect();
+        format!("could not assemble {}\n{}", source.display(), errors.join("\n"))
+    })?;
+
+    let output = output.unwrap_or_else(|| source.with_extension("ch8").display().to_string());
+    fs::write(&output, &assembly.rom).map_err(|msg| format!("could not write {} - {}", output, msg))?;
+    if let Some(symbols) = symbols {
+        fs::write(&symbols, assembly.symbols()).map_err(|msg| format!("could not write {} - {}", symbols, msg))?;
+    }
+    Ok(())
+}
+
+fn list_roms(dir: Option<String>) -> Result<(), String> {
+    let dir = match dir {
+        Some(dir) => dir,
+        // Fall back to the roms bundled with the sources
+        None if !Path::new(ROMS_DIR).is_dir() => format!("{}/{}", env!("CARGO_MANIFEST_DIR"), ROMS_DIR),
+        None => ROMS_DIR.to_string()
+    };
+
+    let entries = fs::read_dir(&dir).map_err(|msg| format!("could not list {} - {}", dir, msg))?;
+    let mut roms: Vec<_> = entries
+        .filter_map(Result::ok)
+        .filter(|entry| entry.path().is_file())
+        .map(|entry| entry.path())
+        .filter(|path| !matches!(path.extension().and_then(OsStr::to_str), Some("quirks") | Some("keymap")))
+        .collect();
+    roms.sort();
+
+    for rom in roms {
+        println!("{}", rom.display());
+    }
+    Ok(())
+}
+
+fn run(options: RunOptions) -> Result<(), String> {
+    let game = load_game(&options.rom)?;
+
+    let (mut settings, _) = rom_settings(options.config.as_deref(), &options.rom, &game)?;
+    apply_options(&mut settings, &options)?;
+
+    let mut chip8 = Chip8::with_quirks(settings.quirks);
+    if let Some(seed) = options.seed {
+        chip8.set_seed(seed);
+    }
+    chip8.set_instruction_cache(options.cache);
+    chip8.load_rom(&game).map_err(|error| error.to_string())?;
+
+    // Where F5 and F9 save and load the state
+    let state_path = match (&options.load_state, &options.rom) {
+        (Some(path), _) => PathBuf::from(path),
+        (None, RomSource::File(rom_path)) => Path::new(rom_path).with_extension("state"),
+        (None, RomSource::Stdin) => PathBuf::from(STATE_FILE)
+    };
+    if options.load_state.is_some() {
+        let state = fs::read(&state_path).map_err(|msg| format!("could not read {} - {}", state_path.display(), msg))?;
+        chip8.load_state(&state).map_err(|error| format!("could not load {} - {}", state_path.display(), error))?;
+    }
+
+    let mut scheduler = match settings.cpu_hz {
+        Some(cpu_hz) => Scheduler::new(cpu_hz.into(), settings.hz.into()),
+        None => Scheduler::with_instructions_per_frame(settings.ipf.into(), settings.hz.into())
+    };
+
+    // A movie needs a known seed, pick one when none was given
+    let movie = options.movie.as_ref().map(|_| {
+        let seed = options.seed.unwrap_or_else(rand::random);
+        chip8.set_seed(seed);
+        Movie::new(&chip8, seed, scheduler.cpu_hz())
+    });
+
+    let mut capture = Capture {
+        stem: match &options.rom {
+            RomSource::File(rom_path) => Path::new(rom_path).with_extension(""),
+            RomSource::Stdin => PathBuf::from(CAPTURE_STEM)
+        },
+        palette: options.capture_theme.unwrap_or(settings.palette),
+        scale: options.capture_scale,
+        format: options.screenshot_format,
+        fps: settings.hz,
+        recording: None
+    };
+    if let Some(path) = &options.record {
+        capture.start_recording(PathBuf::from(path))?;
+    }
+
+    let mut display = graphic::Display::new(settings.scale);
+    display.set_mode(settings.renderer);
+    display.set_palette(settings.palette);
+
+    enable_raw_mode().map_err(|msg| format!("could not set up the terminal - {}", msg))?;
+    let mut keyboard = Keyboard::new(settings.keymap,Duration::from_millis(options.key_timeout));
+    keyboard.enable_release_events();
+    display.enter();
+    fit_terminal(&mut display);
+    display.present(chip8.framebuffer());
+
+    let mut session = Session {
+        keyboard,
+        paused: options.paused,
+        debugger: if options.debug { Some(Debugger::new()) } else { None },
+        state_path,
+        // At most an hour of frames, u64 holds it for any --hz
+        rewind: Rewind::new(usize::try_from(u64::from(options.rewind) * u64::from(settings.hz)).unwrap_or(usize::MAX)),
+        movie,
+        capture
+    };
+    let result = emulate(&mut chip8, &mut scheduler, &mut display, &mut session);
+
+    session.keyboard.disable_release_events();
+    display.shutdown();
+    disable_raw_mode().map_err(|msg| format!("could not restore the terminal - {}", msg))?;
+
+    // Written even when the rom faulted, that is when a movie is most useful
+    if let (Some(mut movie), Some(path)) = (session.movie, &options.movie) {
+        movie.finish(&scheduler);
+        fs::write(path, movie.to_text()).map_err(|msg| format!("could not write {} - {}", path, msg))?;
+    }
+    session.capture.stop_recording()?;
+    result.map_err(|error| describe_fault(&chip8, &error))
+}
+
+/// Error message pointing at the faulting instruction, the PC is left on it by `Chip8::step`
+fn describe_fault(chip8: &Chip8, error: &Chip8Error) -> String {
+    let pc = chip8.cpu().pc;
+    match chip8.cpu().get_next_opcode() {
+        Ok(opcode) => format!("{} - PC {:#05X}, opcode {:04X}", error, pc, opcode),
+        Err(_) => format!("{} - PC {:#05X}", error, pc)
+    }
+}
+
+/// Frontend state of a run, besides the machine and the terminal
+struct Session {
+    keyboard: Keyboard,
+    paused: bool,
+    debugger: Option<Debugger>,
+    // Where F5 and F9 save and load the state
+    state_path: PathBuf,
+    rewind: Rewind,
+    // Input recorded with --movie
+    movie: Option<Movie>,
+    capture: Capture
+}
+
+/// Screenshots and GIF recordings of a run
+struct Capture {
+    // Files are named <stem>-<n>.<extension>, with the first free n
+    stem: PathBuf,
+    palette: Palette,
+    scale: u16,
+    format: ImageFormat,
+    fps: u32,
+    recording: Option<(GifRecorder<BufWriter<File>>, PathBuf)>
+}
+
+impl Capture {
+    fn next_path(&self, extension: &str) -> PathBuf {
+        let stem = self.stem.to_string_lossy();
+        (1..).map(|n| PathBuf::from(format!("{}-{}.{}", stem, n, extension)))
+            .find(|path| !path.exists())
+            .unwrap()
+    }
 
-    let refresh_rate = time::Duration::from_millis(16);
-    let opcodes = opcodes::initialise_opcodes();
+    /// Saves the screen to the next free file, returns its path.
+    fn screenshot(&self, screen: &Framebuffer) -> Result<PathBuf, String> {
+        let path = self.next_path(self.format.extension());
+        let image = capture::screenshot(screen, &self.palette, self.scale, self.format);
+        fs::write(&path, image).map_err(|msg| format!("could not write {} - {}", path.display(), msg))?;
+        Ok(path)
+    }
+
+    fn start_recording(&mut self, path: PathBuf) -> Result<(), String> {
+        let file = File::create(&path).map_err(|msg| format!("could not create {} - {}", path.display(), msg))?;
+        let recorder = GifRecorder::new(BufWriter::new(file), &self.palette, self.scale, self.fps)
+            .map_err(|msg| format!("could not record to {} - {}", path.display(), msg))?;
+        self.recording = Some((recorder, path));
+        Ok(())
+    }
+
+    /// Finishes the recording, if any, returns the path it was saved to.
+    fn stop_recording(&mut self) -> Result<Option<PathBuf>, String> {
+        match self.recording.take() {
+            Some((recorder, path)) => {
+                recorder.finish().and_then(|mut out| out.flush().map_err(|msg| msg.to_string()))
+                    .map_err(|msg| format!("could not write {} - {}", path.display(), msg))?;
+                Ok(Some(path))
+            }
+            None => Ok(None)
+        }
+    }
+}
 
-    let mut cpu = cpu::CPU::new();
-    let mut display = graphic::Display::new();
+fn emulate(chip8: &mut Chip8, scheduler: &mut Scheduler, renderer: &mut impl Renderer, session: &mut Session) -> Result<(), Chip8Error> {
+    let frame_duration = scheduler.frame_duration();
+    let Session { keyboard, paused, debugger, state_path, rewind, movie, capture } = session;
+    // Set while the rewind key is held
+    let mut rewind_until: Option<Instant> = None;
+    rewind.push(chip8);
+    // Last message for the user: save states, debugger faults and prompt errors
+    let mut status = String::new();
 
-    println!("Loading game ..");
-    cpu.memory.append(&mut load_game("./roms/keypadtest.rom"));
-    let remaining_size = 0xFFF - cpu.memory.len();
-    cpu.memory.append(&mut vec![0; usize::from(remaining_size)]);
+    if let Some(debugger) = debugger {
+        draw_panel(renderer, chip8, debugger, &status);
+    }
 
+    let mut last_frame = Instant::now();
+    let mut next_frame = last_frame;
     loop {
-        
-        if !keyboard::update_events(&mut cpu) {
-            return
+        let mut update_screen = false;
+        let keys = chip8.pressed_keys();
+        let action = keyboard.update_events(chip8);
+        if let Some(movie) = movie {
+            movie.record(scheduler, keys, chip8.pressed_keys());
         }
 
-       // cpu.dump_registers();
+        match (action, &mut *debugger) {
+            (Some(Action::Quit), _) => break,
+            (Some(Action::Resize), _) => {
+                fit_terminal(renderer);
+                update_screen = true;
+            }
+            // Going back in time cannot be replayed from the input
+            (Some(Action::LoadState), _) | (Some(Action::Rewind), _) if movie.is_some() => {
+                status = "not available while recording a movie".to_string();
+                update_screen = true;
+            }
+            (Some(Action::Screenshot), _) => {
+                status = match capture.screenshot(chip8.framebuffer()) {
+                    Ok(path) => format!("saved {}", path.display()),
+                    Err(msg) => msg
+                };
+                update_screen = true;
+            }
+            (Some(Action::ToggleRecording), _) => {
+                status = match capture.stop_recording() {
+                    Ok(Some(path)) => format!("saved {}", path.display()),
+                    Ok(None) => {
+                        let path = capture.next_path("gif");
+                        match capture.start_recording(path.clone()) {
+                            Ok(()) => format!("recording to {}, F3 to stop", path.display()),
+                            Err(msg) => msg
+                        }
+                    }
+                    Err(msg) => msg
+                };
+                update_screen = true;
+            }
+            (Some(Action::SaveState), _) => {
+                status = match fs::write(&state_path, chip8.save_state()) {
+                    Ok(()) => format!("saved {}", state_path.display()),
+                    Err(msg) => format!("could not save {} - {}", state_path.display(), msg)
+                };
+                update_screen = true;
+            }
+            (Some(Action::LoadState), _) => {
+                status = match fs::read(&state_path).map_err(|msg| msg.to_string())
+                    .and_then(|state| chip8.load_state(&state).map_err(|error| error.to_string())) {
+                    Ok(()) => format!("loaded {}", state_path.display()),
+                    Err(msg) => format!("could not load {} - {}", state_path.display(), msg)
+                };
+                update_screen = true;
+            }
+            (Some(Action::Rewind), _) => {
+                let hold = if rewind_until.is_some() { REWIND_REPEAT } else { REWIND_FIRST_REPEAT };
+                rewind_until = Some(Instant::now() + hold);
+            }
+            (Some(Action::TogglePause), None) => *paused = !*paused,
+            (Some(Action::TogglePause), Some(debugger)) if debugger.is_paused() => debugger.resume(),
+            (Some(Action::TogglePause), Some(debugger)) => debugger.pause(),
+            (Some(action), Some(debugger)) => {
+                let command = match action {
+                    Action::Step => Some(debugger::Command::Step),
+                    Action::StepOver => Some(debugger::Command::StepOver),
+                    Action::Continue => Some(debugger::Command::Continue),
+                    _ => read_command(renderer, chip8, debugger, &mut status)
+                };
+                if let Some(command) = command {
+                    status.clear();
+                    match debugger.execute(chip8, command) {
+                        Ok(changed) => update_screen = changed,
+                        Err(error) => status = describe_fault(chip8, &error)
+                    }
+                }
+            }
+            _ => {}
+        }
 
-        let update_screen = opcodes::execute_op_code(&mut cpu, &opcodes);
+        let now = Instant::now();
+        let delta = (now - last_frame).min(MAX_CATCH_UP);
+        last_frame = now;
 
-        cpu.update_timers();
+        let rewinding = rewind_until.is_some_and(|until| now < until);
+        if rewinding {
+            update_screen |= rewind.rewind(chip8);
+            status = format!("rewinding, {} frames left", rewind.len());
+        } else {
+            if rewind_until.take().is_some() {
+                status.clear();
+                update_screen = true;
+            }
+            let instructions = scheduler.instructions();
+            match debugger {
+                Some(debugger) => match scheduler.run_until(chip8, delta, |chip8| debugger.should_break(chip8)) {
+                    Ok(changed) => update_screen |= changed,
+                    // Faults stop in the debugger instead of quitting
+                    Err(error) => {
+                        debugger.pause();
+                        status = describe_fault(chip8, &error);
+                    }
+                },
+                None if !*paused => update_screen |= scheduler.run_for(chip8, delta)?,
+                None => {}
+            }
+            // Resuming after a rewind carries on from the frame shown
+            if scheduler.instructions() != instructions {
+                rewind.push(chip8);
+            }
+        }
 
-        cpu.pc += 2;
-        
         if update_screen {
-            graphic::draw_screen(&mut display, &cpu.screen);
+            renderer.present(chip8.framebuffer());
+        }
+        // Every frame, the delays of the GIF follow the frame rate
+        if let Some((recorder, path)) = &mut capture.recording {
+            if let Err(msg) = recorder.add_frame(chip8.framebuffer()) {
+                status = format!("could not write {} - {}", path.display(), msg);
+                capture.recording = None;
+                update_screen = true;
+            }
+        }
+        match debugger {
+            Some(debugger) => draw_panel(renderer, chip8, debugger, &status),
+            None if update_screen => renderer.show_status(&status),
+            None => {}
+        }
+
+        // Sleep until the next frame deadline, the time actually spent is
+        // measured on the next iteration so oversleeping does not drift
+        next_frame += frame_duration;
+        let now = Instant::now();
+        if next_frame > now {
+            thread::sleep(next_frame - now);
+        } else {
+            next_frame = now;
+        }
+    }
+
+    Ok(())
+}
+
+fn replay(movie_path: &str, rom: &RomSource, headless: bool, hz: u32, scale: u16, renderer: RenderMode, screenshot: Option<&str>) -> Result<(), String> {
+    let text = fs::read_to_string(movie_path).map_err(|msg| format!("could not read {} - {}", movie_path, msg))?;
+    let movie = Movie::parse(&text).map_err(|msg| format!("{} - {}", movie_path, msg))?;
+    let game = load_game(rom)?;
+
+    let mut chip8 = Chip8::new();
+    chip8.load_rom(&game).map_err(|error| error.to_string())?;
+    movie.prepare(&mut chip8)?;
+    let mut scheduler = Scheduler::new(movie.cpu_hz, hz.into());
+    let mut replay = Replay::new(&movie);
+
+    if headless {
+        let frame_duration = scheduler.frame_duration();
+        while !replay.is_finished(&scheduler) {
+            replay.run_for(&mut chip8, &mut scheduler, frame_duration)
+                .map_err(|error| describe_fault(&chip8, &error))?;
+        }
+        println!("replayed {} frames, {} instructions", scheduler.timer_ticks(), scheduler.instructions());
+        print!("{}", screen_text(chip8.framebuffer()));
+        return save_screenshot(screenshot, chip8.framebuffer());
+    }
+
+    let mut display = graphic::Display::new(scale);
+    display.set_mode(renderer);
+    enable_raw_mode().map_err(|msg| format!("could not set up the terminal - {}", msg))?;
+    display.enter();
+    fit_terminal(&mut display);
+    display.present(chip8.framebuffer());
+    let result = play(&mut chip8, &mut scheduler, &mut display, &mut replay);
+    display.shutdown();
+    disable_raw_mode().map_err(|msg| format!("could not restore the terminal - {}", msg))?;
+    result.map_err(|error| describe_fault(&chip8, &error))?;
+    save_screenshot(screenshot, chip8.framebuffer())
+}
+
+/// Saves the screen to `path`, in the format of its extension.
+fn save_screenshot(path: Option<&str>, screen: &Framebuffer) -> Result<(), String> {
+    let path = match path {
+        Some(path) => Path::new(path),
+        None => return Ok(())
+    };
+    // Checked when parsing the command line
+    let format = ImageFormat::from_path(path).unwrap_or(ImageFormat::Png);
+    let image = capture::screenshot(screen, &Palette::default(), capture::DEFAULT_SCALE, format);
+    fs::write(path, image).map_err(|msg| format!("could not write {} - {}", path.display(), msg))
+}
+
+const END_OF_MOVIE: &str = "end of the movie, Esc to quit";
+
+/// Plays a movie back on screen, at the recorded speed, until Esc.
+fn play(chip8: &mut Chip8, scheduler: &mut Scheduler, renderer: &mut impl Renderer, replay: &mut Replay) -> Result<(), Chip8Error> {
+    let frame_duration = scheduler.frame_duration();
+    let mut paused = false;
+    let mut finished = false;
+
+    let mut last_frame = Instant::now();
+    let mut next_frame = last_frame;
+    loop {
+        match keyboard::poll_action() {
+            Some(Action::Quit) => break,
+            Some(Action::TogglePause) => paused = !paused,
+            Some(Action::Resize) => {
+                fit_terminal(renderer);
+                renderer.present(chip8.framebuffer());
+                if finished {
+                    renderer.show_status(END_OF_MOVIE);
+                }
+            }
+            _ => {}
+        }
+
+        let now = Instant::now();
+        let delta = (now - last_frame).min(MAX_CATCH_UP);
+        last_frame = now;
+
+        if !paused && !finished {
+            if replay.run_for(chip8, scheduler, delta)? {
+                renderer.present(chip8.framebuffer());
+            }
+            if replay.is_finished(scheduler) {
+                finished = true;
+                renderer.show_status(END_OF_MOVIE);
+            }
+        }
+
+        next_frame += frame_duration;
+        let now = Instant::now();
+        if next_frame > now {
+            thread::sleep(next_frame - now);
+        } else {
+            next_frame = now;
+        }
+    }
+    Ok(())
+}
+
+/// Resizes the renderer to the terminal, as reported by the terminal, the
+/// screen has to be presented again after it.
+fn fit_terminal(renderer: &mut impl Renderer) {
+    if let Ok(size) = terminal::size() {
+        renderer.resize(size);
+    }
+}
+
+/// The screen as text, one character per pixel.
+fn screen_text(screen: &Framebuffer) -> String {
+    let mut text = String::new();
+    for y in 0..screen.height() {
+        for x in 0..screen.width() {
+            text.push(if screen.get(x, y) { '#' } else { '.' });
+        }
+        text.push('\n');
+    }
+    text
+}
+
+/// The debugger panel with `last_line` below it, after a blank line.
+fn panel_lines(chip8: &Chip8, debugger: &Debugger, last_line: String) -> Vec<String> {
+    let mut lines = debugger.panel(chip8);
+    lines.push(String::new());
+    lines.push(last_line);
+    lines
+}
+
+/// Draws the debugger panel beside the screen, with the status line below it.
+fn draw_panel(renderer: &mut impl Renderer, chip8: &Chip8, debugger: &Debugger, status: &str) {
+    renderer.show_panel(&panel_lines(chip8, debugger, status.to_string()));
+}
+
+/// Reads a command at the `:` prompt below the panel, errors end up in `status`.
+fn read_command(renderer: &mut impl Renderer, chip8: &Chip8, debugger: &Debugger, status: &mut String) -> Option<debugger::Command> {
+    let line = keyboard::read_line(|line| {
+        renderer.show_panel(&panel_lines(chip8, debugger, format!(":{}", line)));
+    })?;
+
+    match debugger::Command::parse(&line) {
+        Ok(command) => Some(command),
+        Err(msg) => {
+            *status = msg;
+            None
+        }
+    }
+}
+
+fn main() {
+    let args: Vec<String> = env::args().skip(1).collect();
+
+    let result = match cli::parse_args(&args) {
+        Ok(Command::Run(options)) => run(options),
+        Ok(Command::Disasm { rom, source }) => disasm(&rom, source),
+        Ok(Command::Asm { source, output, symbols }) => asm(&source, output, symbols),
+        Ok(Command::ShowConfig { rom, config }) => show_config(rom, config),
+        Ok(Command::Replay { movie, rom, headless, hz, scale, renderer, screenshot }) => {
+            replay(&movie, &rom, headless, hz, scale, renderer, screenshot.as_deref())
+        }
+        Ok(Command::List(dir)) => list_roms(dir),
+        Ok(Command::Help) => {
+            println!("{}", cli::USAGE);
+            Ok(())
         }
+        Err(msg) => Err(format!("{}\n\n{}", msg, cli::USAGE))
+    };
 
-        thread::sleep(refresh_rate);
+    if let Err(msg) = result {
+        eprintln!("error: {}", msg);
+        process::exit(1);
     }
 }
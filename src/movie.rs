@@ -0,0 +1,290 @@
+use std::fmt::Write;
+use std::time::Duration;
+
+use crate::chip8::Chip8;
+use crate::error::Chip8Error;
+use crate::quirks::Quirks;
+use crate::savestate::RomHash;
+use crate::scheduler::Scheduler;
+
+const HEADER: &str = "chip8r-movie 1";
+
+/// A key press or release, taking effect before the instruction numbered
+/// `instruction` since the start of the run. `frame` is the number of
+/// 60 Hz ticks at that point, for humans reading the file.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct InputEvent {
+    pub frame: u64,
+    pub instruction: u64,
+    pub key: u8,
+    pub pressed: bool
+}
+
+/// Everything needed to reproduce a run from power on: the machine
+/// settings and the input, in a line-based text file.
+///
+/// ```text
+/// chip8r-movie 1
+/// rom 2bcd...
+/// seed 1234
+/// cpu-hz 600
+/// quirks shift_vx=false memory_increment=x+1 vf_reset=true ...
+/// 12 120 press 5
+/// 20 200 release 5
+/// end 300 3000
+/// ```
+#[derive(Debug, Clone, PartialEq)]
+pub struct Movie {
+    pub rom_hash: RomHash,
+    pub seed: u64,
+    pub cpu_hz: u64,
+    pub quirks: Quirks,
+    pub events: Vec<InputEvent>,
+    // Frame and instruction counts when the recording stopped
+    pub end: (u64, u64)
+}
+
+impl Movie {
+    /// An empty movie for a run of `chip8`, which must have been seeded
+    /// with `seed` and not run yet.
+    pub fn new(chip8: &Chip8, seed: u64, cpu_hz: u64) -> Self {
+        Movie {
+            rom_hash: chip8.rom_hash(),
+            seed,
+            cpu_hz,
+            quirks: chip8.quirks(),
+            events: Vec::new(),
+            end: (0, 0)
+        }
+    }
+
+    /// Records the keys that changed between two key masks, see `Chip8::pressed_keys`.
+    pub fn record(&mut self, scheduler: &Scheduler, before: u16, after: u16) {
+        for key in 0..16 {
+            let mask = 1 << key;
+            if before & mask != after & mask {
+                self.events.push(InputEvent {
+                    frame: scheduler.timer_ticks(),
+                    instruction: scheduler.instructions(),
+                    key,
+                    pressed: after & mask != 0
+                });
+            }
+        }
+    }
+
+    /// Marks where the recording stops.
+    pub fn finish(&mut self, scheduler: &Scheduler) {
+        self.end = (scheduler.timer_ticks(), scheduler.instructions());
+    }
+
+    /// Sets up a machine like the one recorded, `chip8` must have the rom loaded.
+    pub fn prepare(&self, chip8: &mut Chip8) -> Result<(), String> {
+        if chip8.rom_hash() != self.rom_hash {
+            return Err("the movie was recorded with a different rom".to_string());
+        }
+        chip8.set_quirks(self.quirks);
+        chip8.set_seed(self.seed);
+        Ok(())
+    }
+
+    pub fn to_text(&self) -> String {
+        let mut text = String::new();
+        let quirks: Vec<String> = self.quirks.fields().iter().map(|(name, value)| format!("{}={}", name, value)).collect();
+
+        writeln!(text, "{}", HEADER).unwrap();
+        writeln!(text, "rom {}", self.rom_hash.iter().map(|byte| format!("{:02x}", byte)).collect::<String>()).unwrap();
+        writeln!(text, "seed {}", self.seed).unwrap();
+        writeln!(text, "cpu-hz {}", self.cpu_hz).unwrap();
+        writeln!(text, "quirks {}", quirks.join(" ")).unwrap();
+        for event in &self.events {
+            let action = if event.pressed { "press" } else { "release" };
+            writeln!(text, "{} {} {} {:X}", event.frame, event.instruction, action, event.key).unwrap();
+        }
+        writeln!(text, "end {} {}", self.end.0, self.end.1).unwrap();
+        text
+    }
+
+    /// Reads a movie written by `to_text`, errors name the line.
+    pub fn parse(text: &str) -> Result<Movie, String> {
+        let mut lines = text.lines().enumerate().map(|(idx, line)| (idx + 1, line.trim())).filter(|(_, line)| !line.is_empty());
+        match lines.next() {
+            Some((_, HEADER)) => {}
+            _ => return Err(format!("not a movie, expected '{}' on the first line", HEADER))
+        }
+
+        let mut movie = Movie {
+            rom_hash: [0; 20],
+            seed: 0,
+            cpu_hz: 0,
+            quirks: Quirks::default(),
+            events: Vec::new(),
+            end: (0, 0)
+        };
+        let mut rom = false;
+        let mut end = false;
+
+        for (number, line) in lines {
+            let error = |msg: &str| format!("line {}: {}", number, msg);
+            let words: Vec<&str> = line.split_whitespace().collect();
+            match words.as_slice() {
+                ["rom", hash] => {
+                    if hash.len() != 40 {
+                        return Err(error("invalid rom hash"));
+                    }
+                    for (idx, byte) in movie.rom_hash.iter_mut().enumerate() {
+                        *byte = u8::from_str_radix(&hash[idx * 2..idx * 2 + 2], 16).map_err(|_| error("invalid rom hash"))?;
+                    }
+                    rom = true;
+                }
+                ["seed", seed] => movie.seed = seed.parse().map_err(|_| error("invalid seed"))?,
+                ["cpu-hz", hz] => movie.cpu_hz = hz.parse().ok().filter(|hz| *hz > 0).ok_or_else(|| error("invalid cpu-hz"))?,
+                ["quirks", fields @ ..] => {
+                    for field in fields {
+                        parse_quirk(&mut movie.quirks, field).ok_or_else(|| error(&format!("invalid quirk '{}'", field)))?;
+                    }
+                }
+                ["end", frame, instruction] => {
+                    movie.end = (frame.parse().map_err(|_| error("invalid end"))?, instruction.parse().map_err(|_| error("invalid end"))?);
+                    end = true;
+                }
+                [frame, instruction, action, key] => {
+                    let event = InputEvent {
+                        frame: frame.parse().map_err(|_| error("invalid frame"))?,
+                        instruction: instruction.parse().map_err(|_| error("invalid instruction count"))?,
+                        key: u8::from_str_radix(key, 16).ok().filter(|key| *key < 16).ok_or_else(|| error("invalid key"))?,
+                        pressed: match *action {
+                            "press" => true,
+                            "release" => false,
+                            _ => return Err(error("expected press or release"))
+                        }
+                    };
+                    if movie.events.last().is_some_and(|last| last.instruction > event.instruction) {
+                        return Err(error("events are out of order"));
+                    }
+                    movie.events.push(event);
+                }
+                _ => return Err(error(&format!("unexpected '{}'", line)))
+            }
+        }
+
+        if !rom || movie.cpu_hz == 0 || !end {
+            return Err("incomplete movie, rom, cpu-hz and end are required".to_string());
+        }
+        Ok(movie)
+    }
+}
+
+fn parse_quirk(quirks: &mut Quirks, field: &str) -> Option<()> {
+    let mut parts = field.splitn(2, '=');
+    quirks.set(parts.next()?, parts.next()?)
+}
+
+/// Plays the input of a movie back into a machine set up with `Movie::prepare`.
+pub struct Replay<'a> {
+    movie: &'a Movie,
+    next_event: usize
+}
+
+impl<'a> Replay<'a> {
+    pub fn new(movie: &'a Movie) -> Self {
+        Replay { movie, next_event: 0 }
+    }
+
+    /// True once the machine has run as far as the recording.
+    pub fn is_finished(&self, scheduler: &Scheduler) -> bool {
+        scheduler.instructions() >= self.movie.end.1
+    }
+
+    /// Like `Scheduler::run_for`, pressing and releasing keys before the
+    /// exact instructions they were recorded at. Stops at the end of the movie.
+    pub fn run_for(&mut self, chip8: &mut Chip8, scheduler: &mut Scheduler, delta: Duration) -> Result<bool, Chip8Error> {
+        let target = scheduler.elapsed() + delta;
+        let mut update_screen = false;
+
+        loop {
+            while let Some(event) = self.movie.events.get(self.next_event) {
+                if event.instruction > scheduler.instructions() {
+                    break;
+                }
+                chip8.set_key(event.key, event.pressed);
+                self.next_event += 1;
+            }
+
+            let stop_at = self.movie.events.get(self.next_event).map(|event| event.instruction).unwrap_or(self.movie.end.1);
+            let mut remaining = stop_at.saturating_sub(scheduler.instructions());
+            let delta = target.saturating_sub(scheduler.elapsed());
+            update_screen |= scheduler.run_until(chip8, delta, |_| {
+                if remaining == 0 {
+                    return true;
+                }
+                remaining -= 1;
+                false
+            })?;
+
+            // Ran out of time before reaching the next event, or the end
+            if remaining > 0 || self.is_finished(scheduler) {
+                return Ok(update_screen);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // LD V0, K - ADD V1, V0 - JP 0x200
+    const ROM: &[u8] = &[0xF0, 0x0A, 0x81, 0x04, 0x12, 0x00];
+
+    fn chip8() -> Chip8 {
+        let mut chip8 = Chip8::new();
+        chip8.load_rom(ROM).unwrap();
+        chip8.set_seed(99);
+        chip8
+    }
+
+    #[test]
+    fn record_and_replay_test() {
+        let mut chip8 = chip8();
+        let mut scheduler = Scheduler::new(600, 60);
+        let mut movie = Movie::new(&chip8, 99, 600);
+
+        // Uneven frames, the replay must not depend on them
+        for (frame, delay) in [7u64, 13, 3, 21, 16, 9].iter().enumerate() {
+            let before = chip8.pressed_keys();
+            chip8.set_key(frame as u8, frame % 2 == 0);
+            movie.record(&scheduler, before, chip8.pressed_keys());
+            scheduler.run_for(&mut chip8, Duration::from_millis(*delay)).unwrap();
+        }
+        movie.finish(&scheduler);
+
+        let movie = Movie::parse(&movie.to_text()).unwrap();
+        let mut replayed = Chip8::new();
+        replayed.load_rom(ROM).unwrap();
+        movie.prepare(&mut replayed).unwrap();
+        let mut replay_scheduler = Scheduler::new(movie.cpu_hz, 60);
+        let mut replay = Replay::new(&movie);
+        while !replay.is_finished(&replay_scheduler) {
+            replay.run_for(&mut replayed, &mut replay_scheduler, Duration::from_millis(16)).unwrap();
+        }
+
+        assert_eq!(scheduler.instructions(), replay_scheduler.instructions());
+        assert!(chip8.snapshot() == replayed.snapshot());
+    }
+
+    #[test]
+    fn parse_errors_test() {
+        let movie = Movie::new(&chip8(), 99, 600).to_text();
+
+        assert!(Movie::parse(&movie).is_ok());
+        assert!(Movie::parse("").is_err());
+        assert!(Movie::parse(&movie.replace("end 0 0", "")).is_err());
+        assert_eq!(Err("line 6: invalid key".to_string()), Movie::parse(&movie.replace("end", "1 10 press G\nend")));
+        assert!(Movie::parse(&movie.replace("vf_reset=true", "vf_reset=maybe")).is_err());
+
+        let mut other = Chip8::new();
+        other.load_rom(&[0x00, 0xE0]).unwrap();
+        assert!(Movie::parse(&movie).unwrap().prepare(&mut other).is_err());
+    }
+}
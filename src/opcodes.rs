@@ -1,386 +1,291 @@
-use crossterm::event::poll;
 use crate::cpu::CPU;
+use crate::error::Chip8Error;
 use crate::instructions;
 use crate::instructions::{Instruction};
 
-pub struct InstructionLookup {
-    pub instruction: Instruction,
-    pub mask: u16,
-    pub id: u16
+// A 12-bit value, the lowest 12 bits of the instruction
+fn addr(opcode: u16) -> u16 {
+    opcode & 0x0FFF
 }
 
-pub struct Variables { 
-    // A 12-bit value, the lowest 12 bits of the instruction
-    addr: u16,
-    // A 4-bit value, the lowest 4 bits of the instruction
-    nibble: u8,
-    // A 4-bit value, the lower 4 bits of the high byte of the instruction
-    x: u8,
-    // A 4-bit value, the upper 4 bits of the low byte of the instruction
-    y: u8,
-    // An 8-bit value, the lowest 8 bits of the instruction
-    kk: u8
+// A 4-bit value, the lowest 4 bits of the instruction
+fn nibble(opcode: u16) -> u8 {
+    (opcode & 0x000F) as u8
 }
 
-fn parse_variables_from_op_code(opcode: &u16) -> Variables {
-    Variables {
-        addr: opcode & 0x0FFF,
-        nibble: (*opcode & 0x000F) as u8,
-        x: ((opcode & 0x0F00) >> 8) as u8,
-        y: ((opcode & 0x00F0) >> 4) as u8,
-        kk: (opcode & 0x00FF) as u8
+// A 4-bit value, the lower 4 bits of the high byte of the instruction
+fn x(opcode: u16) -> u8 {
+    ((opcode & 0x0F00) >> 8) as u8
+}
+
+// A 4-bit value, the upper 4 bits of the low byte of the instruction
+fn y(opcode: u16) -> u8 {
+    ((opcode & 0x00F0) >> 4) as u8
+}
+
+// An 8-bit value, the lowest 8 bits of the instruction
+fn kk(opcode: u16) -> u8 {
+    (opcode & 0x00FF) as u8
+}
+
+type Decoder = fn(u16) -> Option<Instruction>;
+
+// Indexed by the highest nibble of the opcode
+const DECODERS: [Decoder; 16] = [
+    decode_0,
+    |opcode| Some(Instruction::Jp { addr: addr(opcode) }),
+    |opcode| Some(Instruction::Call { addr: addr(opcode) }),
+    |opcode| Some(Instruction::SeByte { x: x(opcode), kk: kk(opcode) }),
+    |opcode| Some(Instruction::SneByte { x: x(opcode), kk: kk(opcode) }),
+    |opcode| match nibble(opcode) {
+        0x0 => Some(Instruction::Se { x: x(opcode), y: y(opcode) }),
+        _ => None
+    },
+    |opcode| Some(Instruction::LdByte { x: x(opcode), kk: kk(opcode) }),
+    |opcode| Some(Instruction::AddByte { x: x(opcode), kk: kk(opcode) }),
+    decode_8,
+    |opcode| match nibble(opcode) {
+        0x0 => Some(Instruction::Sne { x: x(opcode), y: y(opcode) }),
+        _ => None
+    },
+    |opcode| Some(Instruction::LdI { addr: addr(opcode) }),
+    |opcode| Some(Instruction::JpV0 { addr: addr(opcode) }),
+    |opcode| Some(Instruction::Rnd { x: x(opcode), kk: kk(opcode) }),
+    |opcode| Some(Instruction::Drw { x: x(opcode), y: y(opcode), n: nibble(opcode) }),
+    decode_e,
+    decode_f
+];
+
+fn decode_0(opcode: u16) -> Option<Instruction> {
+    match opcode {
+        0x00E0 => Some(Instruction::Cls),
+        0x00EE => Some(Instruction::Ret),
+        _ => Some(Instruction::Sys { addr: addr(opcode) })
     }
 }
 
-fn parse_opcode<'a>(op_codes: &'a Vec<InstructionLookup>, opcode: &u16) -> Option<(&'a Instruction, Variables)> {
-    let op_code = find_opcode_id(&op_codes, &opcode);
-    if op_code == None {
-        return None;
+fn decode_8(opcode: u16) -> Option<Instruction> {
+    let (x, y) = (x(opcode), y(opcode));
+    match nibble(opcode) {
+        0x0 => Some(Instruction::Ld { x, y }),
+        0x1 => Some(Instruction::Or { x, y }),
+        0x2 => Some(Instruction::And { x, y }),
+        0x3 => Some(Instruction::Xor { x, y }),
+        0x4 => Some(Instruction::Add { x, y }),
+        0x5 => Some(Instruction::Sub { x, y }),
+        0x6 => Some(Instruction::Shr { x, y }),
+        0x7 => Some(Instruction::Subn { x, y }),
+        0xE => Some(Instruction::Shl { x, y }),
+        _ => None
     }
-    Some((
-        op_code.unwrap(),
-        parse_variables_from_op_code(opcode)
-    ))
 }
 
-pub fn initialise_opcodes() -> Vec<InstructionLookup> {
-    vec![
-        // CLS
-        InstructionLookup {
-            instruction: Instruction::CLS,
-            mask: 0xFFFF,
-            id: 0x00E0
-        },
-        // RET
-        InstructionLookup {
-            instruction: Instruction::RET,
-            mask: 0xFFFF,
-            id: 0x00EE 
-        },
-        // JP addr
-        InstructionLookup {
-            instruction: Instruction::JP_ADDR,
-            mask: 0xF000,
-            id: 0x1000
-        },
-        // CALL addr
-        InstructionLookup {
-            instruction: Instruction::CALL_ADDR,
-            mask: 0xF000,
-            id: 0x2000 
-        },
-        // SE Vx, byte
-        InstructionLookup {
-            instruction: Instruction::SE_VX_BYTE,
-            mask: 0xF000,
-            id: 0x3000 
-        },
-        // SNE Vx, byte
-        InstructionLookup {
-            instruction: Instruction::SNE_VX_BYTE,
-            mask: 0xF000,
-            id: 0x4000 
-        },
-        // SE Vx, Vy
-        InstructionLookup {
-            instruction: Instruction::SE_VX_VY,
-            mask: 0xF000,
-            id: 0x5000 
-        },
-        // LD Vx, byte
-        InstructionLookup {
-            instruction: Instruction::LD_VX_BYTE,
-            mask: 0xF000,
-            id: 0x6000 
-        },
-        // ADD Vx, byte
-        InstructionLookup {
-            instruction: Instruction::ADD_VX_BYTE,
-            mask: 0xF000,
-            id: 0x7000 
-        },
-        // LD Vx, Vy
-        InstructionLookup {
-            instruction: Instruction::LD_VX_VY,
-            mask: 0xF00F,
-            id: 0x8001
-        },
-        // OR Vx, Vy
-        InstructionLookup {
-            instruction: Instruction::OR_VX_VY,
-            mask: 0xF00F,
-            id: 0x8002
-        },
-        // AND Vx, Vy
-        InstructionLookup {
-            instruction: Instruction::AND_VX_VY,
-            mask: 0xF00F,
-            id: 0x8002
-        },
-        // XOR Vx, Vy
-        InstructionLookup {
-            instruction: Instruction::XOR_VX_VY,
-            mask: 0xF00F,
-            id: 0x8003
-        },
-        // ADD Vx, Vy
-        InstructionLookup {
-            instruction: Instruction::ADD_VX_VY,
-            mask: 0xF00F,
-            id: 0x8004
-        },
-        // SUB Vx, Vy
-        InstructionLookup {
-            instruction: Instruction::SUB_VX_VY,
-            mask: 0xF00F,
-            id: 0x8005
-        },
-        // SHR Vx {, Vy}
-        InstructionLookup {
-            instruction: Instruction::SHR_VX_VY,
-            mask: 0xF00F,
-            id: 0x8006
-        },
-        // SUBN Vx, Vy
-        InstructionLookup {
-            instruction: Instruction::SUBN_VX_VY,
-            mask: 0xF00F,
-            id: 0x8007
-        },
-        // SHL Vx {, Vy}
-        InstructionLookup {
-            instruction: Instruction::SHL_VX_VY,
-            mask: 0xF00F,
-            id: 0x800E
-        },
-        // SNE Vx, Vy
-        InstructionLookup {
-            instruction: Instruction::SNE_VX_VY,
-            mask: 0xF00F,
-            id: 0x9000
-        },
-        // LD I, addr
-        InstructionLookup {
-            instruction: Instruction::LD_I_ADDR,
-            mask: 0xF000,
-            id: 0xA000
-        },
-        // JP V0, addr
-        InstructionLookup {
-            instruction: Instruction::JP_V0_ADDR,
-            mask: 0xF000,
-            id: 0xB000
-        },
-        // RND Vx, byte
-        InstructionLookup {
-            instruction: Instruction::RND_VX_BYTE,
-            mask: 0xF000,
-            id: 0xC000
-        },
-        // DRW Vx, Vy, nibble
-        InstructionLookup {
-            instruction: Instruction::DRW,
-            mask: 0xF000,
-            id: 0xD000
-        },
-        // SKP Vx
-        InstructionLookup {
-            instruction: Instruction::SKP_VX,
-            mask: 0xF0FF,
-            id: 0xE09E
-        },
-        // SKNP Vx
-        InstructionLookup {
-            instruction: Instruction::SKNP_VX,
-            mask: 0xF0FF,
-            id: 0xE0A1
-        },
-        // LD Vx, DT
-        InstructionLookup {
-            instruction: Instruction::LD_VX_DT,
-            mask: 0xF0FF,
-            id: 0xF007
-        },
-        // LD Vx, K
-        InstructionLookup {
-            instruction: Instruction::LD_VX_K,
-            mask: 0xF0FF,
-            id: 0xF00A
-        },
-        // LD DT, Vx
-        InstructionLookup {
-            instruction: Instruction::LD_DT_VX,
-            mask: 0xF0FF,
-            id: 0xF015 
-        },
-        // LD ST, Vx
-        InstructionLookup {
-            instruction: Instruction::LD_ST_VX,
-            mask: 0xF0FF,
-            id: 0xF018 
-        },
-        // ADD I, Vx
-        InstructionLookup {
-            instruction: Instruction::ADD_I_VX,
-            mask: 0xF0FF,
-            id: 0xF01E 
-        },
-        // LD F, Vx
-        InstructionLookup {
-            instruction: Instruction::LD_F_VX,
-            mask: 0xF0FF,
-            id: 0xF029 
-        },
-        // LD B, Vx
-        InstructionLookup {
-            instruction: Instruction::LD_B_VX,
-            mask: 0xF0FF,
-            id: 0xF033 
-        },
-        // LD [I], Vx
-        InstructionLookup {
-            instruction: Instruction::LD_I_VX,
-            mask: 0xF0FF,
-            id: 0xF055 
-        },
-        // LD Vx, [I]
-        InstructionLookup {
-            instruction: Instruction::LD_VX_I,
-            mask: 0xF0FF,
-            id: 0xF065 
-        }
-    ]
+fn decode_e(opcode: u16) -> Option<Instruction> {
+    let x = x(opcode);
+    match kk(opcode) {
+        0x9E => Some(Instruction::Skp { x }),
+        0xA1 => Some(Instruction::Sknp { x }),
+        _ => None
+    }
 }
 
-pub fn find_opcode_id<'a>(opcodes: &'a Vec<InstructionLookup>, opcode: &u16) -> Option<&'a Instruction> {
-    for opcode_def in opcodes.iter() {
-        if opcode & opcode_def.mask == opcode_def.id {
-            return Some(&opcode_def.instruction);
-        }
+fn decode_f(opcode: u16) -> Option<Instruction> {
+    let x = x(opcode);
+    match kk(opcode) {
+        0x07 => Some(Instruction::LdVxDt { x }),
+        0x0A => Some(Instruction::LdVxK { x }),
+        0x15 => Some(Instruction::LdDtVx { x }),
+        0x18 => Some(Instruction::LdStVx { x }),
+        0x1E => Some(Instruction::AddI { x }),
+        0x29 => Some(Instruction::LdF { x }),
+        0x33 => Some(Instruction::LdB { x }),
+        0x55 => Some(Instruction::LdIVx { x }),
+        0x65 => Some(Instruction::LdVxI { x }),
+        _ => None
     }
-    None
 }
 
-pub fn execute_op_code(cpu: &mut CPU, op_codes: &Vec<InstructionLookup>) -> bool {
-    let opcode = cpu.get_next_opcode();
-    let parse_result = parse_opcode(op_codes, &opcode);
-    
-    if parse_result.is_none() {
-        return false;
+/// Decodes an opcode, None when it is not a valid instruction.
+pub fn decode(opcode: u16) -> Option<Instruction> {
+    DECODERS[usize::from(opcode >> 12)](opcode)
+}
+
+/// Encodes an instruction back to its opcode, the inverse of `decode`.
+/// Operands are truncated to their field width.
+pub fn encode(instruction: &Instruction) -> u16 {
+    fn xy(high: u16, x: u8, y: u8, low: u16) -> u16 {
+        high << 12 | u16::from(x & 0xF) << 8 | u16::from(y & 0xF) << 4 | low
+    }
+    fn xkk(high: u16, x: u8, kk: u8) -> u16 {
+        high << 12 | u16::from(x & 0xF) << 8 | u16::from(kk)
+    }
+    fn nnn(high: u16, addr: u16) -> u16 {
+        high << 12 | addr & 0x0FFF
     }
 
-    let (instruction, variables) = parse_result.unwrap();
+    match *instruction {
+        Instruction::Sys { addr } => nnn(0x0, addr),
+        Instruction::Cls => 0x00E0,
+        Instruction::Ret => 0x00EE,
+        Instruction::Jp { addr } => nnn(0x1, addr),
+        Instruction::Call { addr } => nnn(0x2, addr),
+        Instruction::SeByte { x, kk } => xkk(0x3, x, kk),
+        Instruction::SneByte { x, kk } => xkk(0x4, x, kk),
+        Instruction::Se { x, y } => xy(0x5, x, y, 0x0),
+        Instruction::LdByte { x, kk } => xkk(0x6, x, kk),
+        Instruction::AddByte { x, kk } => xkk(0x7, x, kk),
+        Instruction::Ld { x, y } => xy(0x8, x, y, 0x0),
+        Instruction::Or { x, y } => xy(0x8, x, y, 0x1),
+        Instruction::And { x, y } => xy(0x8, x, y, 0x2),
+        Instruction::Xor { x, y } => xy(0x8, x, y, 0x3),
+        Instruction::Add { x, y } => xy(0x8, x, y, 0x4),
+        Instruction::Sub { x, y } => xy(0x8, x, y, 0x5),
+        Instruction::Shr { x, y } => xy(0x8, x, y, 0x6),
+        Instruction::Subn { x, y } => xy(0x8, x, y, 0x7),
+        Instruction::Shl { x, y } => xy(0x8, x, y, 0xE),
+        Instruction::Sne { x, y } => xy(0x9, x, y, 0x0),
+        Instruction::LdI { addr } => nnn(0xA, addr),
+        Instruction::JpV0 { addr } => nnn(0xB, addr),
+        Instruction::Rnd { x, kk } => xkk(0xC, x, kk),
+        Instruction::Drw { x, y, n } => xy(0xD, x, y, u16::from(n & 0xF)),
+        Instruction::Skp { x } => xkk(0xE, x, 0x9E),
+        Instruction::Sknp { x } => xkk(0xE, x, 0xA1),
+        Instruction::LdVxDt { x } => xkk(0xF, x, 0x07),
+        Instruction::LdVxK { x } => xkk(0xF, x, 0x0A),
+        Instruction::LdDtVx { x } => xkk(0xF, x, 0x15),
+        Instruction::LdStVx { x } => xkk(0xF, x, 0x18),
+        Instruction::AddI { x } => xkk(0xF, x, 0x1E),
+        Instruction::LdF { x } => xkk(0xF, x, 0x29),
+        Instruction::LdB { x } => xkk(0xF, x, 0x33),
+        Instruction::LdIVx { x } => xkk(0xF, x, 0x55),
+        Instruction::LdVxI { x } => xkk(0xF, x, 0x65)
+    }
+}
 
-    // println!("Executing Instruction {:?} from opcode - {:?} - {:?} - {:?} - {:?} - {:?} - {:?}", instruction, opcode, variables.x, variables.y, variables.kk, variables.addr, variables.nibble);
+/// Decodes and executes an opcode.
+/// Returns true when the screen has changed.
+pub fn execute_op_code(cpu: &mut CPU, opcode: &u16) -> Result<bool, Chip8Error> {
+    match decode(*opcode) {
+        Some(instruction) => execute(cpu, &instruction),
+        None => Err(Chip8Error::UnknownOpcode { addr: cpu.pc, opcode: *opcode })
+    }
+}
+
+/// Executes a decoded instruction.
+/// Returns true when the screen has changed.
+pub fn execute(cpu: &mut CPU, instruction: &Instruction) -> Result<bool, Chip8Error> {
+    // println!("Executing Instruction {:?}", instruction);
 
-    match instruction {
-        Instruction::CLS => {
-            return instructions::clear_screen(cpu);
+    match *instruction {
+        Instruction::Sys { .. } => {},
+        Instruction::Cls => {
+            return Ok(instructions::clear_screen(cpu));
         },
-        Instruction::RET => {
-            instructions::ret(cpu);
+        Instruction::Ret => {
+            instructions::ret(cpu)?;
         },
-        Instruction::JP_ADDR => {
-            instructions::jp_addr(cpu, variables.addr);
+        Instruction::Jp { addr } => {
+            instructions::jp_addr(cpu, addr);
         },
-        Instruction::CALL_ADDR => {
-            instructions::call_addr(cpu, variables.addr);
+        Instruction::Call { addr } => {
+            instructions::call_addr(cpu, addr)?;
         },
-        Instruction::SE_VX_BYTE => {
-            instructions::se_xv_byte(cpu, variables.x, variables.kk);
+        Instruction::SeByte { x, kk } => {
+            instructions::se_xv_byte(cpu, x, kk);
         },
-        Instruction::SNE_VX_BYTE => {
-            instructions::sne_xv_byte(cpu, variables.x, variables.kk);
+        Instruction::SneByte { x, kk } => {
+            instructions::sne_xv_byte(cpu, x, kk);
         },
-        Instruction::SE_VX_VY => {
-            instructions::se_vx_vy(cpu, variables.x, variables.y);
+        Instruction::Se { x, y } => {
+            instructions::se_vx_vy(cpu, x, y);
         },
-        Instruction::LD_VX_BYTE => {
-            instructions::ld_vx_byte(cpu, variables.x, variables.kk);
+        Instruction::LdByte { x, kk } => {
+            instructions::ld_vx_byte(cpu, x, kk);
         },
-        Instruction::ADD_VX_BYTE => {
-            instructions::add_vx_byte(cpu, variables.x, variables.kk);
+        Instruction::AddByte { x, kk } => {
+            instructions::add_vx_byte(cpu, x, kk);
         },
-        Instruction::LD_VX_VY => {
-            instructions::ld_vx_vy(cpu, variables.x, variables.y);
+        Instruction::Ld { x, y } => {
+            instructions::ld_vx_vy(cpu, x, y);
         },
-        Instruction::OR_VX_VY => {
-            instructions::or_vx_vy(cpu, variables.x, variables.y);
+        Instruction::Or { x, y } => {
+            instructions::or_vx_vy(cpu, x, y);
         },
-        Instruction::AND_VX_VY => {
-            instructions::and_vx_vy(cpu, variables.x, variables.y);
+        Instruction::And { x, y } => {
+            instructions::and_vx_vy(cpu, x, y);
         },
-        Instruction::XOR_VX_VY => {
-            instructions::xor_vx_vy(cpu, variables.x, variables.y);
+        Instruction::Xor { x, y } => {
+            instructions::xor_vx_vy(cpu, x, y);
         },
-        Instruction::ADD_VX_VY => {
-            instructions::add_vx_vy(cpu, variables.x, variables.y);
+        Instruction::Add { x, y } => {
+            instructions::add_vx_vy(cpu, x, y);
         },
-        Instruction::SUB_VX_VY => {
-            instructions::sub_vx_vy(cpu, variables.x, variables.y);
+        Instruction::Sub { x, y } => {
+            instructions::sub_vx_vy(cpu, x, y);
         },
-        Instruction::SHR_VX_VY => {
-            instructions::shr_vx_vy(cpu, variables.x, variables.y);
+        Instruction::Shr { x, y } => {
+            instructions::shr_vx_vy(cpu, x, y);
         },
-        Instruction::SUBN_VX_VY => {
-            instructions::subn_vx_vy(cpu, variables.x, variables.y);
+        Instruction::Subn { x, y } => {
+            instructions::subn_vx_vy(cpu, x, y);
         },
-        Instruction::SHL_VX_VY => {
-            instructions::shl_vx_vy(cpu, variables.x, variables.y);
+        Instruction::Shl { x, y } => {
+            instructions::shl_vx_vy(cpu, x, y);
         },
-        Instruction::SNE_VX_VY => {
-            instructions::sne_vx_vy(cpu, variables.x, variables.y);
+        Instruction::Sne { x, y } => {
+            instructions::sne_vx_vy(cpu, x, y);
         },
-        Instruction::LD_I_ADDR => {
-            instructions::ld_i_addr(cpu, variables.addr);
+        Instruction::LdI { addr } => {
+            instructions::ld_i_addr(cpu, addr);
         },
-        Instruction::JP_V0_ADDR => {
-            instructions::jp_v0_addr(cpu, variables.addr);
+        Instruction::JpV0 { addr } => {
+            instructions::jp_v0_addr(cpu, addr);
         },
-        Instruction::RND_VX_BYTE => {
-            instructions::random_vx_byte(cpu, variables.x, variables.kk);
+        Instruction::Rnd { x, kk } => {
+            instructions::random_vx_byte(cpu, x, kk);
         },
-        Instruction::DRW => {
-            return instructions::draw(cpu, variables.x, variables.y, variables.nibble);
+        Instruction::Drw { x, y, n } => {
+            return instructions::draw(cpu, x, y, n);
         }
-        Instruction::SKP_VX => {
-            instructions::skip_vx(cpu, variables.x);
+        Instruction::Skp { x } => {
+            instructions::skip_vx(cpu, x);
         },
-        Instruction::SKNP_VX => {
-            instructions::skipn_vx(cpu, variables.x);
+        Instruction::Sknp { x } => {
+            instructions::skipn_vx(cpu, x);
         },
-        Instruction::LD_VX_K => {
-            instructions::load_vx_k(cpu, variables.x);
+        Instruction::LdVxK { x } => {
+            instructions::load_vx_k(cpu, x);
         },
-        Instruction::LD_B_VX => {
-            instructions::load_bytes_vx(cpu, variables.x);
+        Instruction::LdB { x } => {
+            instructions::load_bytes_vx(cpu, x)?;
         },
-        Instruction::LD_VX_I => {
-            instructions::load_vx_i(cpu, variables.x);
+        Instruction::LdVxI { x } => {
+            instructions::load_vx_i(cpu, x)?;
         },
-        Instruction::LD_I_VX => {
-            instructions::load_i_vx(cpu, variables.x);
+        Instruction::LdIVx { x } => {
+            instructions::load_i_vx(cpu, x)?;
         },
-        Instruction::LD_F_VX => {
-            instructions::load_f_vx(cpu, variables.x);
+        Instruction::LdF { x } => {
+            instructions::load_f_vx(cpu, x);
         },
-        Instruction::ADD_I_VX => {
-            instructions::add_i_vx(cpu, variables.x);
+        Instruction::AddI { x } => {
+            instructions::add_i_vx(cpu, x);
         },
-        Instruction::LD_ST_VX => {
-            instructions::load_st_vx(cpu, variables.x);
+        Instruction::LdStVx { x } => {
+            instructions::load_st_vx(cpu, x);
         },
-        Instruction::LD_DT_VX => {
-            instructions::load_dt_vx(cpu, variables.x);
+        Instruction::LdDtVx { x } => {
+            instructions::load_dt_vx(cpu, x);
         },
-        Instruction::LD_VX_DT => {
-            instructions::load_vx_dt(cpu, variables.x);
+        Instruction::LdVxDt { x } => {
+            instructions::load_vx_dt(cpu, x);
         }
     };
-    false
+    Ok(false)
 }
 
 #[cfg(test)]
+#[allow(non_snake_case)]
 mod tests {
     use super::*;
     use super::super::cpu;
@@ -388,52 +293,46 @@ mod tests {
     #[test]
     fn op_code_CLS() {
         let opcode = 0x00E0;
-        find_op_code_test(opcode, Instruction::CLS);
+        find_op_code_test(opcode, Instruction::Cls);
     }
 
     #[test]
     fn op_code_RET() {
         let opcode = 0x00EE;
-        find_op_code_test(opcode, Instruction::RET);
+        find_op_code_test(opcode, Instruction::Ret);
     }
 
     #[test]
     fn op_code_JP_ADDR() {
         let opcode = 0x1000;
-        find_op_code_test(opcode, Instruction::JP_ADDR);
+        find_op_code_test(opcode, Instruction::Jp { addr: 0x000 });
     }
 
     #[test]
     fn op_code_CALL_ADDR() {
         let opcode = 0x2000;
-        find_op_code_test(opcode, Instruction::CALL_ADDR);
+        find_op_code_test(opcode, Instruction::Call { addr: 0x000 });
     }
 
     fn find_op_code_test(opcode: u16, expected_op_code: Instruction) {
-        let op_codes = initialise_opcodes();
-        let op_code_id = find_opcode_id(&op_codes, &opcode).unwrap();
-        assert_eq!(expected_op_code, *op_code_id);
+        let op_code_id = decode(opcode).unwrap();
+        assert_eq!(expected_op_code, op_code_id);
     }
 
     #[test]
     fn parse_opcode_test() {
-        let op_codes = initialise_opcodes();
-        let (op_code, variable) = parse_opcode(&op_codes, &0x42FC).unwrap();
-
-        assert_eq!(Instruction::SNE_VX_BYTE, *op_code);
-        assert_eq!(0x2, variable.x);
-        assert_eq!(0x0F, variable.y);
-        assert_eq!(0x0C, variable.nibble);
-        assert_eq!(0x02FC, variable.addr);
-        assert_eq!(0xFC, variable.kk);
+        let op_code = decode(0x42FC).unwrap();
+
+        assert_eq!(Instruction::SneByte { x: 0x2, kk: 0xFC }, op_code);
+        assert_eq!(Instruction::Drw { x: 0x2, y: 0xF, n: 0xC }, decode(0xD2FC).unwrap());
+        assert_eq!(Instruction::LdI { addr: 0x2FC }, decode(0xA2FC).unwrap());
     }
 
     #[test]
     fn execute_LD_VX_BYTE_test() {
         let mut cpu = cpu::CPU::new();
-        let op_codes = initialise_opcodes();
 
-        execute_op_code(&mut cpu, &op_codes, &0x6100);
+        execute_op_code(&mut cpu, &0x6100).unwrap();
 
         assert_eq![0x00, cpu.registers[1]];
     }
@@ -441,10 +340,71 @@ mod tests {
     #[test]
     fn execute_LD_I_ADDR_test() {
         let mut cpu = cpu::CPU::new();
-        let op_codes = initialise_opcodes();
 
-        execute_op_code(&mut cpu, &op_codes, &0xa2d8);
+        execute_op_code(&mut cpu, &0xa2d8).unwrap();
 
         assert_eq![0x2d8, cpu.i];
     }
+
+    #[test]
+    fn op_code_8XY_test() {
+        find_op_code_test(0x8120, Instruction::Ld { x: 1, y: 2 });
+        find_op_code_test(0x8121, Instruction::Or { x: 1, y: 2 });
+        find_op_code_test(0x8122, Instruction::And { x: 1, y: 2 });
+        assert_eq!(None, decode(0x8128));
+    }
+
+    #[test]
+    fn decode_encode_round_trip_test() {
+        let mut valid = 0;
+        for opcode in 0..=0xFFFF {
+            if let Some(instruction) = decode(opcode) {
+                assert_eq!(opcode, encode(&instruction), "{:04X} decoded as {:?}", opcode, instruction);
+                assert_eq!(Some(instruction), decode(encode(&instruction)));
+                valid += 1;
+            }
+        }
+
+        // 0nnn, 1nnn, 2nnn, Annn, Bnnn: 5 * 4096
+        // 3xkk, 4xkk, 6xkk, 7xkk, Cxkk, Dxyn: 6 * 4096
+        // 5xy0, 9xy0: 2 * 256, 8xy_: 9 * 256
+        // Ex__: 2 * 16, Fx__: 9 * 16
+        assert_eq!(11 * 4096 + 11 * 256 + 11 * 16, valid);
+    }
+
+    #[test]
+    fn execute_errors_test() {
+        let mut cpu = cpu::CPU::new();
+
+        assert_eq!(Err(Chip8Error::StackUnderflow), execute_op_code(&mut cpu, &0x00EE));
+        assert_eq!(Err(Chip8Error::UnknownOpcode { addr: 0x200, opcode: 0xFFFF }), execute_op_code(&mut cpu, &0xFFFF));
+
+        for _ in 0..16 {
+            execute_op_code(&mut cpu, &0x2200).unwrap();
+        }
+        assert_eq!(Err(Chip8Error::StackOverflow), execute_op_code(&mut cpu, &0x2200));
+
+        cpu.i = 0xFFE;
+        assert_eq!(Err(Chip8Error::MemoryOutOfBounds { addr: 0x1000 }), execute_op_code(&mut cpu, &0xF233));
+        assert_eq!(Err(Chip8Error::MemoryOutOfBounds { addr: 0x1000 }), execute_op_code(&mut cpu, &0xF355));
+        assert_eq!(Err(Chip8Error::MemoryOutOfBounds { addr: 0x1000 }), execute_op_code(&mut cpu, &0xF365));
+    }
+
+    #[test]
+    fn faults_leave_state_unchanged_test() {
+        let mut cpu = cpu::CPU::new();
+        for x in 0..4 {
+            cpu.set_register_value(x, 0x10 + x);
+        }
+        cpu.i = 0xFFE;
+        let before = cpu;
+
+        // Bcd, Fx55 and Fx65 overrun memory after their first bytes
+        for opcode in [0xF233, 0xF355, 0xF365] {
+            assert!(execute_op_code(&mut cpu, &opcode).is_err());
+            assert_eq!(before.memory[0xFFE..], cpu.memory[0xFFE..]);
+            assert_eq!(before.registers, cpu.registers);
+            assert_eq!(0xFFE, cpu.i);
+        }
+    }
 }
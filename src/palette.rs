@@ -0,0 +1,226 @@
+use std::env;
+
+use crossterm::style::Color;
+
+pub const THEME_NAMES: &[&str] = &["default", "green", "amber", "lcd", "high-contrast"];
+
+// Colour names accepted in the config, besides #rrggbb
+const COLORS: &[(&str, Color)] = &[
+    ("default", Color::Reset),
+    ("black", Color::Black),
+    ("dark_grey", Color::DarkGrey),
+    ("red", Color::Red),
+    ("dark_red", Color::DarkRed),
+    ("green", Color::Green),
+    ("dark_green", Color::DarkGreen),
+    ("yellow", Color::Yellow),
+    ("dark_yellow", Color::DarkYellow),
+    ("blue", Color::Blue),
+    ("dark_blue", Color::DarkBlue),
+    ("magenta", Color::Magenta),
+    ("dark_magenta", Color::DarkMagenta),
+    ("cyan", Color::Cyan),
+    ("dark_cyan", Color::DarkCyan),
+    ("white", Color::White),
+    ("grey", Color::Grey)
+];
+
+// Channel levels of the 6x6x6 colour cube of 256 colour terminals
+const CUBE_LEVELS: [u8; 6] = [0, 95, 135, 175, 215, 255];
+
+const fn rgb(value: u32) -> Color {
+    Color::Rgb { r: (value >> 16) as u8, g: (value >> 8) as u8, b: value as u8 }
+}
+
+/// Colours of the pixels, indexed by the bits of the pixel in the XO-CHIP
+/// bitplanes: off, first plane, second plane, both planes.
+///
+/// The screen has a single plane so far, only the first two colours are
+/// drawn.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Palette {
+    pub colors: [Color; 4]
+}
+
+impl Palette {
+    /// White on the terminal background
+    pub const DEFAULT: Palette = Palette { colors: [Color::Reset, Color::White, Color::DarkGrey, Color::Grey] };
+
+    /// Green phosphor monitor
+    pub const GREEN: Palette = Palette { colors: [rgb(0x001400), rgb(0x33ff33), rgb(0x1a8c1a), rgb(0xaaffaa)] };
+
+    /// Amber monochrome monitor
+    pub const AMBER: Palette = Palette { colors: [rgb(0x1a0d00), rgb(0xffb000), rgb(0x8c5a00), rgb(0xffdd88)] };
+
+    /// Dark pixels on a green-grey LCD
+    pub const LCD: Palette = Palette { colors: [rgb(0x9bbc0f), rgb(0x0f380f), rgb(0x5a7a14), rgb(0x306230)] };
+
+    /// The basic terminal colours, black and white
+    pub const HIGH_CONTRAST: Palette = Palette { colors: [Color::Black, Color::White, Color::Yellow, Color::Cyan] };
+
+    /// Looks up a theme from one of `THEME_NAMES`.
+    pub fn from_name(name: &str) -> Option<Palette> {
+        match name.trim().to_lowercase().as_str() {
+            "default" => Some(Palette::DEFAULT),
+            "green" => Some(Palette::GREEN),
+            "amber" => Some(Palette::AMBER),
+            "lcd" => Some(Palette::LCD),
+            "high-contrast" => Some(Palette::HIGH_CONTRAST),
+            _ => None
+        }
+    }
+
+    /// The name of the theme this palette is, if any.
+    pub fn name(&self) -> Option<&'static str> {
+        THEME_NAMES.iter().copied().find(|name| Palette::from_name(name) == Some(*self))
+    }
+
+    pub fn off(&self) -> Color {
+        self.colors[0]
+    }
+
+    pub fn on(&self) -> Color {
+        self.colors[1]
+    }
+
+    /// The colours as RGB, for images. The terminal colours are taken as
+    /// xterm draws them, Reset as black when off and white otherwise.
+    pub fn rgb(&self) -> [[u8; 3]; 4] {
+        let mut rgb = [[0; 3]; 4];
+        for (idx, color) in self.colors.iter().enumerate() {
+            rgb[idx] = to_rgb(*color).unwrap_or(if idx == 0 { [0, 0, 0] } else { [255, 255, 255] });
+        }
+        rgb
+    }
+
+    /// The palette with the 24-bit colours replaced by the closest of the
+    /// 256 colour palette, for terminals without 24-bit colour.
+    pub fn to_ansi_256(&self) -> Palette {
+        Palette { colors: self.colors.map(ansi_256) }
+    }
+}
+
+impl Default for Palette {
+    fn default() -> Self {
+        Palette::DEFAULT
+    }
+}
+
+/// Whether the terminal draws 24-bit colours, as advertised in `COLORTERM`.
+pub fn supports_truecolor() -> bool {
+    env::var("COLORTERM").is_ok_and(|value| value == "truecolor" || value == "24bit")
+}
+
+/// A colour as RGB, None for Reset.
+fn to_rgb(color: Color) -> Option<[u8; 3]> {
+    // The 16 basic colours of xterm, in the order of their ANSI values
+    const BASIC: [[u8; 3]; 16] = [
+        [0, 0, 0], [205, 0, 0], [0, 205, 0], [205, 205, 0], [0, 0, 238], [205, 0, 205], [0, 205, 205], [229, 229, 229],
+        [127, 127, 127], [255, 0, 0], [0, 255, 0], [255, 255, 0], [92, 92, 255], [255, 0, 255], [0, 255, 255], [255, 255, 255]
+    ];
+    let value = match color {
+        Color::Reset => return None,
+        Color::Rgb { r, g, b } => return Some([r, g, b]),
+        Color::AnsiValue(value) => value,
+        Color::Black => 0,
+        Color::DarkRed => 1,
+        Color::DarkGreen => 2,
+        Color::DarkYellow => 3,
+        Color::DarkBlue => 4,
+        Color::DarkMagenta => 5,
+        Color::DarkCyan => 6,
+        Color::Grey => 7,
+        Color::DarkGrey => 8,
+        Color::Red => 9,
+        Color::Green => 10,
+        Color::Yellow => 11,
+        Color::Blue => 12,
+        Color::Magenta => 13,
+        Color::Cyan => 14,
+        Color::White => 15
+    };
+    Some(match value {
+        0..=15 => BASIC[usize::from(value)],
+        16..=231 => {
+            let value = value - 16;
+            [CUBE_LEVELS[usize::from(value / 36)], CUBE_LEVELS[usize::from(value / 6 % 6)], CUBE_LEVELS[usize::from(value % 6)]]
+        }
+        _ => [8 + (value - 232) * 10; 3]
+    })
+}
+
+/// The closest colour of the 256 colour palette, from the colour cube or the
+/// grey ramp.
+fn ansi_256(color: Color) -> Color {
+    let (r, g, b) = match color {
+        Color::Rgb { r, g, b } => (r, g, b),
+        color => return color
+    };
+    let distance = |(r2, g2, b2): (u8, u8, u8)| {
+        [(r, r2), (g, g2), (b, b2)].iter().map(|(a, b)| (i32::from(*a) - i32::from(*b)).pow(2)).sum::<i32>()
+    };
+    let level = |channel: u8| {
+        (0..6).min_by_key(|idx| (i32::from(CUBE_LEVELS[*idx]) - i32::from(channel)).abs()).unwrap()
+    };
+
+    let (lr, lg, lb) = (level(r), level(g), level(b));
+    let cube = (CUBE_LEVELS[lr], CUBE_LEVELS[lg], CUBE_LEVELS[lb]);
+    // 24 greys from 8 to 238
+    let grey_idx = ((i32::from(r) + i32::from(g) + i32::from(b)) / 3 - 8).clamp(0, 230) as u8 / 10;
+    let grey = 8 + grey_idx * 10;
+
+    if distance((grey, grey, grey)) < distance(cube) {
+        Color::AnsiValue(232 + grey_idx)
+    } else {
+        Color::AnsiValue(16 + 36 * lr as u8 + 6 * lg as u8 + lb as u8)
+    }
+}
+
+/// A colour name from the config, or #rrggbb.
+pub fn parse_color(text: &str) -> Option<Color> {
+    let text = text.trim().to_lowercase();
+    if let Some(hex) = text.strip_prefix('#') {
+        let value = u32::from_str_radix(hex, 16).ok().filter(|_| hex.len() == 6)?;
+        return Some(rgb(value));
+    }
+    COLORS.iter().find(|(name, _)| *name == text).map(|(_, color)| *color)
+}
+
+/// The name `parse_color` reads back.
+pub fn color_name(color: Color) -> String {
+    match color {
+        Color::Rgb { r, g, b } => format!("#{:02x}{:02x}{:02x}", r, g, b),
+        color => COLORS.iter().find(|(_, named)| *named == color).map(|(name, _)| name.to_string()).unwrap_or_default()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn themes_test() {
+        for name in THEME_NAMES {
+            assert_eq!(Some(*name), Palette::from_name(name).unwrap().name());
+        }
+        assert_eq!(Some(Palette::HIGH_CONTRAST), Palette::from_name(" High-Contrast"));
+        assert_eq!(None, Palette::from_name("sepia"));
+        assert_eq!([[0, 0, 0], [255, 255, 255], [127, 127, 127], [229, 229, 229]], Palette::DEFAULT.rgb());
+    }
+
+    #[test]
+    fn ansi_256_test() {
+        assert_eq!(Color::AnsiValue(16), ansi_256(rgb(0x000000)));
+        assert_eq!(Color::AnsiValue(231), ansi_256(rgb(0xffffff)));
+        assert_eq!(Color::AnsiValue(214), ansi_256(rgb(0xffb000)));
+        // Greys are closer on the grey ramp than in the cube
+        assert_eq!(Color::AnsiValue(244), ansi_256(rgb(0x808080)));
+        assert_eq!(Color::White, ansi_256(Color::White));
+
+        assert_eq!(Some([255, 175, 0]), to_rgb(Color::AnsiValue(214)));
+        assert_eq!(Some([128, 128, 128]), to_rgb(Color::AnsiValue(244)));
+
+        let palette = Palette::AMBER.to_ansi_256();
+        assert!(palette.colors.iter().all(|color| matches!(color, Color::AnsiValue(_))));
+    }
+}
@@ -0,0 +1,167 @@
+/// How far Fx55 and Fx65 move I after the registers have been copied
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum MemoryIncrement {
+    // I += x + 1, the original COSMAC VIP behavior
+    XPlusOne,
+    // I += x, CHIP-48
+    X,
+    // I is left untouched, SUPER-CHIP
+    None
+}
+
+/// Behaviors that differ between CHIP-8 interpreters.
+///
+/// ROMs usually rely on the behavior of the interpreter they were written
+/// for, see https://github.com/Timendus/chip8-test-suite#quirks-test
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Quirks {
+    // 8xy6 and 8xyE shift Vx in place instead of shifting Vy into Vx
+    pub shift_vx: bool,
+    // Effect of Fx55 and Fx65 on I
+    pub memory_increment: MemoryIncrement,
+    // 8xy1, 8xy2 and 8xy3 reset VF to 0
+    pub vf_reset: bool,
+    // Bxnn jumps to xnn + Vx instead of nnn + V0
+    pub jump_vx: bool,
+    // Sprites are clipped at the edges of the screen instead of wrapping around
+    pub clip_sprites: bool,
+    // DRW waits for the next 60 Hz tick, limiting drawing to one sprite per frame
+    pub display_wait: bool,
+    // Fx0A completes when a key is pressed instead of when it is released
+    pub key_on_press: bool
+}
+
+pub const PROFILE_NAMES: &[&str] = &["vip", "chip48", "schip", "xochip"];
+
+/// Names of the fields, as used by `Quirks::set` and `Quirks::fields`
+pub const FIELD_NAMES: &[&str] = &["shift_vx", "memory_increment", "vf_reset", "jump_vx", "clip_sprites", "display_wait", "key_on_press"];
+
+impl Quirks {
+    /// The original COSMAC VIP interpreter
+    pub const VIP: Quirks = Quirks {
+        shift_vx: false,
+        memory_increment: MemoryIncrement::XPlusOne,
+        vf_reset: true,
+        jump_vx: false,
+        clip_sprites: true,
+        display_wait: true,
+        key_on_press: false
+    };
+
+    /// CHIP-48 on the HP-48 calculators
+    pub const CHIP48: Quirks = Quirks {
+        shift_vx: true,
+        memory_increment: MemoryIncrement::X,
+        vf_reset: false,
+        jump_vx: true,
+        clip_sprites: true,
+        display_wait: false,
+        key_on_press: true
+    };
+
+    /// SUPER-CHIP 1.1
+    pub const SCHIP: Quirks = Quirks {
+        shift_vx: true,
+        memory_increment: MemoryIncrement::None,
+        vf_reset: false,
+        jump_vx: true,
+        clip_sprites: true,
+        display_wait: false,
+        key_on_press: true
+    };
+
+    /// XO-CHIP, as implemented by Octo
+    pub const XOCHIP: Quirks = Quirks {
+        shift_vx: false,
+        memory_increment: MemoryIncrement::XPlusOne,
+        vf_reset: false,
+        jump_vx: false,
+        clip_sprites: false,
+        display_wait: false,
+        key_on_press: false
+    };
+
+    /// Looks up a profile from one of `PROFILE_NAMES`.
+    pub fn from_name(name: &str) -> Option<Quirks> {
+        match name.trim().to_lowercase().as_str() {
+            "vip" => Some(Quirks::VIP),
+            "chip48" => Some(Quirks::CHIP48),
+            "schip" => Some(Quirks::SCHIP),
+            "xochip" => Some(Quirks::XOCHIP),
+            _ => None
+        }
+    }
+
+    /// Sets one of the `FIELD_NAMES` from its text, `true` or `false`, or
+    /// `x+1`, `x` or `none` for memory_increment. None when either is invalid.
+    pub fn set(&mut self, name: &str, value: &str) -> Option<()> {
+        let flag = || value.parse::<bool>().ok();
+        match name {
+            "shift_vx" => self.shift_vx = flag()?,
+            "memory_increment" => self.memory_increment = match value {
+                "x+1" => MemoryIncrement::XPlusOne,
+                "x" => MemoryIncrement::X,
+                "none" => MemoryIncrement::None,
+                _ => return None
+            },
+            "vf_reset" => self.vf_reset = flag()?,
+            "jump_vx" => self.jump_vx = flag()?,
+            "clip_sprites" => self.clip_sprites = flag()?,
+            "display_wait" => self.display_wait = flag()?,
+            "key_on_press" => self.key_on_press = flag()?,
+            _ => return None
+        }
+        Some(())
+    }
+
+    /// Each of the `FIELD_NAMES` with its value, in the text `set` reads.
+    pub fn fields(&self) -> Vec<(&'static str, String)> {
+        let memory_increment = match self.memory_increment {
+            MemoryIncrement::XPlusOne => "x+1",
+            MemoryIncrement::X => "x",
+            MemoryIncrement::None => "none"
+        };
+        vec![
+            ("shift_vx", self.shift_vx.to_string()),
+            ("memory_increment", memory_increment.to_string()),
+            ("vf_reset", self.vf_reset.to_string()),
+            ("jump_vx", self.jump_vx.to_string()),
+            ("clip_sprites", self.clip_sprites.to_string()),
+            ("display_wait", self.display_wait.to_string()),
+            ("key_on_press", self.key_on_press.to_string())
+        ]
+    }
+}
+
+impl Default for Quirks {
+    fn default() -> Self {
+        Quirks::VIP
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn from_name_test() {
+        for name in PROFILE_NAMES {
+            assert!(Quirks::from_name(name).is_some());
+        }
+        assert_eq!(Some(Quirks::SCHIP), Quirks::from_name(" SCHIP\n"));
+        assert_eq!(None, Quirks::from_name("nes"));
+    }
+
+    #[test]
+    fn fields_test() {
+        let mut quirks = Quirks::VIP;
+        for (name, value) in Quirks::SCHIP.fields() {
+            quirks.set(name, &value).unwrap();
+        }
+        assert_eq!(Quirks::SCHIP, quirks);
+        assert_eq!(FIELD_NAMES, Quirks::VIP.fields().iter().map(|(name, _)| *name).collect::<Vec<_>>().as_slice());
+
+        assert_eq!(None, quirks.set("memory_increment", "x+2"));
+        assert_eq!(None, quirks.set("turbo", "true"));
+    }
+}
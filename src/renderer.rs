@@ -0,0 +1,171 @@
+use crate::framebuffer::Framebuffer;
+
+/// Where frames end up: the terminal, nowhere, or memory for tests.
+///
+/// Frontends only talk to the screen through this trait, so a renderer can
+/// be swapped without touching the emulator core.
+pub trait Renderer {
+    /// Takes over the output, before the first frame.
+    fn enter(&mut self) {}
+
+    /// Shows a frame.
+    fn present(&mut self, screen: &Framebuffer);
+
+    /// The output is now `size` columns and rows, in the units of the renderer.
+    /// The next frame is drawn in full.
+    fn resize(&mut self, size: (u16, u16));
+
+    /// Restores the output, the renderer is not used afterwards.
+    fn shutdown(&mut self);
+
+    /// Shows a line for the user below the screen, renderers without text
+    /// drop it.
+    fn show_status(&mut self, _status: &str) {}
+
+    /// Shows lines of text beside the screen, top down, such as the
+    /// debugger panel. Renderers without text drop them.
+    fn show_panel(&mut self, _lines: &[String]) {}
+}
+
+/// Drops every frame, for benchmarks and CI.
+#[derive(Debug, Default)]
+pub struct NullRenderer {
+    frames: u64
+}
+
+impl NullRenderer {
+    pub fn new() -> Self {
+        NullRenderer::default()
+    }
+
+    /// Number of frames presented.
+    pub fn frames(&self) -> u64 {
+        self.frames
+    }
+}
+
+impl Renderer for NullRenderer {
+    fn present(&mut self, _screen: &Framebuffer) {
+        self.frames += 1;
+    }
+
+    fn resize(&mut self, _size: (u16, u16)) {}
+
+    fn shutdown(&mut self) {}
+}
+
+/// Keeps everything it is given, for tests to assert against.
+#[derive(Debug, Default)]
+pub struct MemoryRenderer {
+    frames: Vec<Framebuffer>,
+    size: Option<(u16, u16)>,
+    status: String,
+    panel: Vec<String>,
+    shut_down: bool
+}
+
+impl MemoryRenderer {
+    pub fn new() -> Self {
+        MemoryRenderer::default()
+    }
+
+    /// Every frame presented, oldest first.
+    pub fn frames(&self) -> &[Framebuffer] {
+        &self.frames
+    }
+
+    pub fn last_frame(&self) -> Option<&Framebuffer> {
+        self.frames.last()
+    }
+
+    /// The size given to the last resize.
+    pub fn size(&self) -> Option<(u16, u16)> {
+        self.size
+    }
+
+    pub fn status(&self) -> &str {
+        &self.status
+    }
+
+    /// The lines given to the last `show_panel`.
+    pub fn panel(&self) -> &[String] {
+        &self.panel
+    }
+
+    pub fn is_shut_down(&self) -> bool {
+        self.shut_down
+    }
+}
+
+impl Renderer for MemoryRenderer {
+    fn present(&mut self, screen: &Framebuffer) {
+        self.frames.push(*screen);
+    }
+
+    fn resize(&mut self, size: (u16, u16)) {
+        self.size = Some(size);
+    }
+
+    fn shutdown(&mut self) {
+        self.shut_down = true;
+    }
+
+    fn show_status(&mut self, status: &str) {
+        self.status = status.to_string();
+    }
+
+    fn show_panel(&mut self, lines: &[String]) {
+        self.panel = lines.to_vec();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::chip8::Chip8;
+    use crate::scheduler::Scheduler;
+
+    // Draws the 0 digit at 0,0 then 8,0 and stops on a jump to itself
+    const ROM: &[u8] = &[0x60, 0x00, 0xF0, 0x29, 0xD1, 0x25, 0x71, 0x08, 0xD1, 0x25, 0x12, 0x0A];
+
+    /// Runs `frames` frames, presenting the screen when it changed.
+    fn run_frames(renderer: &mut impl Renderer, frames: u32) {
+        let mut chip8 = Chip8::new();
+        chip8.load_rom(ROM).unwrap();
+        let mut scheduler = Scheduler::with_instructions_per_frame(10, 60);
+        for _ in 0..frames {
+            if scheduler.run_for(&mut chip8, scheduler.frame_duration()).unwrap() {
+                renderer.present(chip8.framebuffer());
+            }
+        }
+        renderer.shutdown();
+    }
+
+    #[test]
+    fn memory_renderer_test() {
+        let mut renderer = MemoryRenderer::new();
+        run_frames(&mut renderer, 10);
+
+        assert!(renderer.is_shut_down());
+        let screen = renderer.last_frame().unwrap();
+        assert!(screen.get(0, 0) && screen.get(8, 0));
+        assert_eq!(0xF0F0 << 48, screen.row(0));
+        // The two sprites may land in one frame or two, never more
+        assert!((1..=2).contains(&renderer.frames().len()));
+
+        renderer.resize((80, 24));
+        renderer.show_status("paused");
+        renderer.show_panel(&["PC 0x20A".to_string()]);
+        assert_eq!((Some((80, 24)), "paused"), (renderer.size(), renderer.status()));
+        assert_eq!(["PC 0x20A"], renderer.panel());
+    }
+
+    #[test]
+    fn null_renderer_test() {
+        let mut renderer = NullRenderer::new();
+        let mut memory = MemoryRenderer::new();
+        run_frames(&mut renderer, 10);
+        run_frames(&mut memory, 10);
+        assert_eq!(memory.frames().len() as u64, renderer.frames());
+    }
+}
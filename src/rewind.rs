@@ -0,0 +1,188 @@
+use std::collections::VecDeque;
+use std::convert::TryInto;
+
+use crate::chip8::Chip8;
+
+// Unchanged bytes between two changes below this are copied into the same
+// patch, a patch header costs 4 bytes
+const MIN_GAP: usize = 4;
+
+/// Ring buffer of the last frames, to step the machine back in time.
+///
+/// Only the newest state is kept whole, as a save state. Every older frame
+/// is a patch turning the frame after it back into it, so the oldest frame
+/// can be dropped without touching the others. Most frames only change a
+/// few registers and screen rows, their patch is a few hundred bytes.
+pub struct Rewind {
+    capacity: usize,
+    newest: Option<Vec<u8>>,
+    // deltas[n] turns frame n + 1 into frame n, the last one gives the frame before newest
+    deltas: VecDeque<Vec<u8>>
+}
+
+impl Rewind {
+    /// Keeps up to `capacity` frames, counting the newest one.
+    pub fn new(capacity: usize) -> Self {
+        Rewind {
+            capacity,
+            newest: None,
+            deltas: VecDeque::new()
+        }
+    }
+
+    /// Records the state of the machine at the end of a frame.
+    pub fn push(&mut self, chip8: &Chip8) {
+        if self.capacity == 0 {
+            return;
+        }
+
+        let state = chip8.save_state();
+        if let Some(previous) = self.newest.take() {
+            self.deltas.push_back(diff(&state, &previous));
+            if self.deltas.len() >= self.capacity {
+                self.deltas.pop_front();
+            }
+        }
+        self.newest = Some(state);
+    }
+
+    /// Puts the machine back one frame, returns false when there is no
+    /// older frame. Frames pushed afterwards replace the ones rewound.
+    pub fn rewind(&mut self, chip8: &mut Chip8) -> bool {
+        match (self.deltas.pop_back(), &mut self.newest) {
+            (Some(delta), Some(newest)) => {
+                apply(&delta, newest);
+                // The state came from this machine, it has the right rom
+                chip8.load_state(newest).expect("rewind state");
+                true
+            }
+            _ => false
+        }
+    }
+
+    /// Number of frames that can be rewound.
+    pub fn len(&self) -> usize {
+        self.deltas.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.deltas.is_empty()
+    }
+
+    pub fn clear(&mut self) {
+        self.newest = None;
+        self.deltas.clear();
+    }
+
+    /// Bytes used by the stored frames.
+    pub fn memory_used(&self) -> usize {
+        self.newest.as_ref().map(Vec::len).unwrap_or(0) + self.deltas.iter().map(Vec::len).sum::<usize>()
+    }
+}
+
+/// Patch turning `from` into `to`: the length of `to`, then runs of
+/// offset:u16 len:u16 bytes.
+fn diff(from: &[u8], to: &[u8]) -> Vec<u8> {
+    let mut delta = (to.len() as u32).to_be_bytes().to_vec();
+    let differs = |idx: usize| from.get(idx) != to.get(idx);
+
+    let mut idx = 0;
+    while idx < to.len() {
+        if !differs(idx) {
+            idx += 1;
+            continue;
+        }
+
+        let start = idx;
+        let mut end = idx + 1;
+        while end < to.len() && end - start < usize::from(u16::MAX) && (end..(end + MIN_GAP).min(to.len())).any(differs) {
+            end += 1;
+        }
+
+        delta.extend_from_slice(&(start as u16).to_be_bytes());
+        delta.extend_from_slice(&((end - start) as u16).to_be_bytes());
+        delta.extend_from_slice(&to[start..end]);
+        idx = end;
+    }
+    delta
+}
+
+fn apply(delta: &[u8], state: &mut Vec<u8>) {
+    let len = u32::from_be_bytes(delta[..4].try_into().unwrap()) as usize;
+    state.resize(len, 0);
+
+    let mut rest = &delta[4..];
+    while !rest.is_empty() {
+        let start = usize::from(u16::from_be_bytes([rest[0], rest[1]]));
+        let count = usize::from(u16::from_be_bytes([rest[2], rest[3]]));
+        state[start..start + count].copy_from_slice(&rest[4..4 + count]);
+        rest = &rest[4 + count..];
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // LD V0, 0x01 - ADD V0, 0x01 - CALL 0x200
+    const ROM: &[u8] = &[0x60, 0x01, 0x70, 0x01, 0x22, 0x00];
+
+    fn chip8() -> Chip8 {
+        let mut chip8 = Chip8::new();
+        chip8.load_rom(ROM).unwrap();
+        chip8
+    }
+
+    #[test]
+    fn rewind_test() {
+        let mut chip8 = chip8();
+        let mut rewind = Rewind::new(10);
+
+        let mut frames = Vec::new();
+        for _ in 0..5 {
+            chip8.run_cycles(3).unwrap();
+            rewind.push(&chip8);
+            frames.push(chip8.snapshot());
+        }
+        assert_eq!(4, rewind.len());
+
+        for frame in frames.iter().rev().skip(1) {
+            assert!(rewind.rewind(&mut chip8));
+            assert!(chip8.snapshot() == *frame);
+        }
+        assert!(!rewind.rewind(&mut chip8));
+
+        // Carries on from the frame rewound to
+        chip8.run_cycles(3).unwrap();
+        rewind.push(&chip8);
+        assert_eq!(1, rewind.len());
+    }
+
+    #[test]
+    fn capacity_test() {
+        let mut chip8 = chip8();
+        let mut rewind = Rewind::new(3);
+
+        for _ in 0..10 {
+            chip8.run_cycles(3).unwrap();
+            rewind.push(&chip8);
+        }
+        assert_eq!(2, rewind.len());
+        // Patches only hold the few bytes that changed
+        assert!(rewind.memory_used() < chip8.save_state().len() + 200);
+    }
+
+    #[test]
+    fn diff_test() {
+        let from = [1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12];
+        let to = [1, 0, 3, 0, 5, 6, 7, 8, 9, 10, 11, 0, 13];
+
+        let mut state = from.to_vec();
+        apply(&diff(&from, &to), &mut state);
+        assert_eq!(to.to_vec(), state);
+
+        let mut state = to.to_vec();
+        apply(&diff(&to, &from), &mut state);
+        assert_eq!(from.to_vec(), state);
+    }
+}
@@ -0,0 +1,75 @@
+/// Random number generator owned by the machine state, so runs can be
+/// reproduced from a seed and saved along with the rest of the state.
+/// xorshift64*, the sequence only depends on the seed and the number of calls.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Rng {
+    state: u64
+}
+
+impl Rng {
+    pub fn new(seed: u64) -> Self {
+        let mut rng = Rng { state: 0 };
+        rng.set_seed(seed);
+        rng
+    }
+
+    /// Restarts the sequence.
+    pub fn set_seed(&mut self, seed: u64) {
+        // xorshift gets stuck on 0, splitmix the seed into a non-zero state
+        let mut z = seed.wrapping_add(0x9E37_79B9_7F4A_7C15);
+        z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+        self.state = (z ^ (z >> 31)).max(1);
+    }
+
+    pub fn next_byte(&mut self) -> u8 {
+        (self.next_u64() >> 56) as u8
+    }
+
+    fn next_u64(&mut self) -> u64 {
+        self.state ^= self.state >> 12;
+        self.state ^= self.state << 25;
+        self.state ^= self.state >> 27;
+        self.state.wrapping_mul(0x2545_F491_4F6C_DD1D)
+    }
+
+    /// The generator state as bytes, for save states.
+    pub fn to_bytes(&self) -> [u8; 8] {
+        self.state.to_be_bytes()
+    }
+
+    /// Reads bytes written by `to_bytes`, None when they are invalid.
+    pub fn from_bytes(bytes: &[u8; 8]) -> Option<Self> {
+        let state = u64::from_be_bytes(*bytes);
+        if state == 0 {
+            return None;
+        }
+        Some(Rng { state })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn bytes(rng: &mut Rng, n: usize) -> Vec<u8> {
+        (0..n).map(|_| rng.next_byte()).collect()
+    }
+
+    #[test]
+    fn seed_test() {
+        assert_eq!(bytes(&mut Rng::new(42), 16), bytes(&mut Rng::new(42), 16));
+        assert_ne!(bytes(&mut Rng::new(42), 16), bytes(&mut Rng::new(43), 16));
+        // Not stuck on a zero seed
+        assert!(bytes(&mut Rng::new(0), 16).iter().any(|byte| *byte != 0));
+    }
+
+    #[test]
+    fn bytes_test() {
+        let mut rng = Rng::new(7);
+        rng.next_byte();
+
+        assert_eq!(Some(rng), Rng::from_bytes(&rng.to_bytes()));
+        assert_eq!(None, Rng::from_bytes(&[0; 8]));
+    }
+}
@@ -0,0 +1,258 @@
+use std::error::Error;
+use std::fmt;
+
+use crate::cpu::{KeyWait, CPU, MAX_STACK_SIZE, MEMORY_SIZE};
+use crate::graphic::HEIGHT;
+use crate::quirks::{MemoryIncrement, Quirks};
+use crate::rng::Rng;
+
+/// SHA-1 of a rom, identifies the rom a state was taken with.
+pub type RomHash = [u8; 20];
+
+const MAGIC: &[u8; 4] = b"C8RS";
+pub const VERSION: u16 = 1;
+
+/// Reasons a save state cannot be loaded
+#[derive(Debug, Clone, PartialEq)]
+pub enum StateError {
+    // Not a chip8r save state
+    BadMagic,
+    // Written by a newer or older chip8r
+    UnsupportedVersion(u16),
+    // Taken with another rom than the one loaded
+    RomMismatch,
+    // The file ends before the state does
+    Truncated,
+    // A field holds a value no machine can be in
+    Invalid(&'static str)
+}
+
+impl fmt::Display for StateError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            StateError::BadMagic => write!(f, "not a save state"),
+            StateError::UnsupportedVersion(version) => write!(f, "unsupported save state version {}, expected {}", version, VERSION),
+            StateError::RomMismatch => write!(f, "save state was taken with a different rom"),
+            StateError::Truncated => write!(f, "save state is truncated"),
+            StateError::Invalid(field) => write!(f, "save state has an invalid {}", field)
+        }
+    }
+}
+
+impl Error for StateError {}
+
+pub fn rom_hash(rom: &[u8]) -> RomHash {
+    sha1_smol::Sha1::from(rom).digest().bytes()
+}
+
+/// Serializes the machine state, big-endian:
+///
+/// ```text
+/// "C8RS" version:u16 rom_hash:[u8; 20]
+/// memory:[u8; 4096] pc:u16 i:u16 registers:[u8; 16]
+/// stack_size:u8 sp:u8 stack:[u16; sp]
+/// dt:u8 st:u8 vblank:u8 keys:u16
+/// screen:[u64; 32] quirks:[u8; 7] rng:[u8; 8]
+/// key_wait:u8 (0xFF when none, else the register) held:u16 pressed:u8 (0xFF when none)
+/// ```
+///
+/// Debugging state such as watchpoints is not saved.
+pub fn encode(cpu: &CPU, rom_hash: &RomHash) -> Vec<u8> {
+    let mut bytes = Vec::with_capacity(MEMORY_SIZE + 512);
+    bytes.extend_from_slice(MAGIC);
+    bytes.extend_from_slice(&VERSION.to_be_bytes());
+    bytes.extend_from_slice(rom_hash);
+
+    bytes.extend_from_slice(&cpu.memory);
+    bytes.extend_from_slice(&cpu.pc.to_be_bytes());
+    bytes.extend_from_slice(&cpu.i.to_be_bytes());
+    bytes.extend_from_slice(&cpu.registers);
+
+    bytes.push(cpu.stack_size);
+    bytes.push(cpu.sp);
+    for addr in &cpu.stack[..usize::from(cpu.sp)] {
+        bytes.extend_from_slice(&addr.to_be_bytes());
+    }
+
+    bytes.push(cpu.dt);
+    bytes.push(cpu.st);
+    bytes.push(cpu.vblank as u8);
+    bytes.extend_from_slice(&cpu.keys.to_be_bytes());
+
+    for y in 0..HEIGHT {
+        bytes.extend_from_slice(&cpu.screen.row(y).to_be_bytes());
+    }
+
+    let quirks = &cpu.quirks;
+    let memory_increment = match quirks.memory_increment {
+        MemoryIncrement::XPlusOne => 0,
+        MemoryIncrement::X => 1,
+        MemoryIncrement::None => 2
+    };
+    bytes.extend_from_slice(&[
+        quirks.shift_vx as u8,
+        memory_increment,
+        quirks.vf_reset as u8,
+        quirks.jump_vx as u8,
+        quirks.clip_sprites as u8,
+        quirks.display_wait as u8,
+        quirks.key_on_press as u8
+    ]);
+    bytes.extend_from_slice(&cpu.rng.to_bytes());
+
+    let wait = cpu.key_wait.unwrap_or(KeyWait { x: 0xFF, held: 0, pressed: None });
+    bytes.push(wait.x);
+    bytes.extend_from_slice(&wait.held.to_be_bytes());
+    bytes.push(wait.pressed.unwrap_or(0xFF));
+    bytes
+}
+
+/// Reads a state written by `encode` on top of `cpu`, which keeps whatever
+/// is not part of the state. Fails when it was taken with another rom.
+pub fn decode(bytes: &[u8], rom_hash: &RomHash, cpu: &CPU) -> Result<CPU, StateError> {
+    let mut reader = Reader { bytes };
+    if reader.take(MAGIC.len())? != MAGIC {
+        return Err(StateError::BadMagic);
+    }
+    let version = reader.u16()?;
+    if version != VERSION {
+        return Err(StateError::UnsupportedVersion(version));
+    }
+    if reader.take(rom_hash.len())? != rom_hash {
+        return Err(StateError::RomMismatch);
+    }
+
+    let mut cpu = *cpu;
+    cpu.memory.copy_from_slice(reader.take(MEMORY_SIZE)?);
+    cpu.pc = reader.u16()?;
+    cpu.i = reader.u16()?;
+    cpu.registers.copy_from_slice(reader.take(16)?);
+
+    cpu.stack_size = reader.u8()?;
+    cpu.sp = reader.u8()?;
+    if cpu.stack_size == 0 || usize::from(cpu.stack_size) > MAX_STACK_SIZE || cpu.sp > cpu.stack_size {
+        return Err(StateError::Invalid("stack"));
+    }
+    cpu.stack = [0; MAX_STACK_SIZE];
+    for idx in 0..usize::from(cpu.sp) {
+        cpu.stack[idx] = reader.u16()?;
+    }
+
+    cpu.dt = reader.u8()?;
+    cpu.st = reader.u8()?;
+    cpu.vblank = reader.bool("vblank")?;
+    cpu.keys = reader.u16()?;
+
+    for y in 0..HEIGHT {
+        cpu.screen.set_row(y, u64::from_be_bytes(reader.array()?));
+    }
+
+    cpu.quirks = Quirks {
+        shift_vx: reader.bool("quirk")?,
+        memory_increment: match reader.u8()? {
+            0 => MemoryIncrement::XPlusOne,
+            1 => MemoryIncrement::X,
+            2 => MemoryIncrement::None,
+            _ => return Err(StateError::Invalid("quirk"))
+        },
+        vf_reset: reader.bool("quirk")?,
+        jump_vx: reader.bool("quirk")?,
+        clip_sprites: reader.bool("quirk")?,
+        display_wait: reader.bool("quirk")?,
+        key_on_press: reader.bool("quirk")?
+    };
+    cpu.rng = Rng::from_bytes(&reader.array()?).ok_or(StateError::Invalid("rng"))?;
+    let (x, held, pressed) = (reader.u8()?, reader.u16()?, reader.u8()?);
+    cpu.key_wait = match (x, pressed) {
+        (0xFF, _) => None,
+        (x, 0xFF) if x < 16 => Some(KeyWait { x, held, pressed: None }),
+        (x, key) if x < 16 && key < 16 => Some(KeyWait { x, held, pressed: Some(key) }),
+        _ => return Err(StateError::Invalid("key wait"))
+    };
+
+    cpu.memory_writes = None;
+    cpu.watch_hit = None;
+    Ok(cpu)
+}
+
+struct Reader<'a> {
+    bytes: &'a [u8]
+}
+
+impl<'a> Reader<'a> {
+    fn take(&mut self, len: usize) -> Result<&'a [u8], StateError> {
+        if self.bytes.len() < len {
+            return Err(StateError::Truncated);
+        }
+        let (head, tail) = self.bytes.split_at(len);
+        self.bytes = tail;
+        Ok(head)
+    }
+
+    fn array<const N: usize>(&mut self) -> Result<[u8; N], StateError> {
+        let mut array = [0; N];
+        array.copy_from_slice(self.take(N)?);
+        Ok(array)
+    }
+
+    fn u8(&mut self) -> Result<u8, StateError> {
+        Ok(self.take(1)?[0])
+    }
+
+    fn u16(&mut self) -> Result<u16, StateError> {
+        Ok(u16::from_be_bytes(self.array()?))
+    }
+
+    fn bool(&mut self, field: &'static str) -> Result<bool, StateError> {
+        match self.u8()? {
+            0 => Ok(false),
+            1 => Ok(true),
+            _ => Err(StateError::Invalid(field))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn round_trip_test() {
+        let mut cpu = CPU::new();
+        cpu.pc = 0x2A4;
+        cpu.i = 0x3F0;
+        cpu.registers[0xA] = 0x42;
+        cpu.push_stack(0x206).unwrap();
+        cpu.dt = 12;
+        cpu.keys = 0b1000_0000_0010;
+        cpu.screen.set(63, 31, true);
+        cpu.quirks = Quirks::SCHIP;
+        cpu.rng.next_byte();
+        cpu.key_wait = Some(KeyWait { x: 3, held: 0b10, pressed: Some(1) });
+        let hash = rom_hash(b"rom");
+
+        let bytes = encode(&cpu, &hash);
+        let decoded = decode(&bytes, &hash, &CPU::new()).unwrap();
+        assert!(decoded == cpu);
+    }
+
+    #[test]
+    fn errors_test() {
+        let cpu = CPU::new();
+        let hash = rom_hash(b"rom");
+        let bytes = encode(&cpu, &hash);
+
+        assert_eq!(Some(StateError::RomMismatch), decode(&bytes, &rom_hash(b"other"), &cpu).err());
+        assert_eq!(Some(StateError::Truncated), decode(&bytes[..bytes.len() - 1], &hash, &cpu).err());
+        assert_eq!(Some(StateError::BadMagic), decode(b"PNG.", &hash, &cpu).err());
+
+        let mut newer = bytes.clone();
+        newer[5] = 99;
+        assert_eq!(Some(StateError::UnsupportedVersion(99)), decode(&newer, &hash, &cpu).err());
+    }
+
+    #[test]
+    fn rom_hash_test() {
+        assert_eq!("a9993e364706816aba3e25717850c26c9cd0d89d", rom_hash(b"abc").iter().map(|byte| format!("{:02x}", byte)).collect::<String>());
+    }
+}
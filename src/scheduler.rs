@@ -0,0 +1,170 @@
+use std::time::Duration;
+
+use crate::chip8::Chip8;
+use crate::error::Chip8Error;
+
+/// Rate of the delay and sound timers, fixed by the CHIP-8 spec
+pub const TIMER_HZ: u64 = 60;
+
+const NANOS_PER_SEC: u128 = 1_000_000_000;
+
+/// Keeps the CPU clock and the 60 Hz timers in step with wall-clock time.
+///
+/// The number of instructions and timer ticks due is always computed from
+/// the total emulated time rather than accumulated per frame, so rounding
+/// errors never add up and long sessions stay in sync.
+pub struct Scheduler {
+    cpu_hz: u64,
+    frame_rate: u64,
+    // Emulated time since the scheduler started
+    elapsed: Duration,
+    instructions: u64,
+    timer_ticks: u64
+}
+
+impl Scheduler {
+    /// `cpu_hz` instructions per second, rendering `frame_rate` frames per second.
+    pub fn new(cpu_hz: u64, frame_rate: u64) -> Self {
+        Scheduler {
+            cpu_hz,
+            frame_rate,
+            elapsed: Duration::from_secs(0),
+            instructions: 0,
+            timer_ticks: 0
+        }
+    }
+
+    /// `instructions_per_frame` instructions for each of the `frame_rate` frames per second.
+    pub fn with_instructions_per_frame(instructions_per_frame: u64, frame_rate: u64) -> Self {
+        Self::new(instructions_per_frame * frame_rate, frame_rate)
+    }
+
+    pub fn frame_duration(&self) -> Duration {
+        Duration::from_secs(1) / self.frame_rate as u32
+    }
+
+    pub fn cpu_hz(&self) -> u64 {
+        self.cpu_hz
+    }
+
+    /// Emulated time since the scheduler started.
+    pub fn elapsed(&self) -> Duration {
+        self.elapsed
+    }
+
+    pub fn instructions(&self) -> u64 {
+        self.instructions
+    }
+
+    pub fn timer_ticks(&self) -> u64 {
+        self.timer_ticks
+    }
+
+    /// Advances the machine by `delta` of wall-clock time, running every
+    /// instruction and timer tick that falls into it in chronological order.
+    /// Returns true when the screen has changed.
+    pub fn run_for(&mut self, chip8: &mut Chip8, delta: Duration) -> Result<bool, Chip8Error> {
+        self.run_until(chip8, delta, |_| false)
+    }
+
+    /// Like `run_for`, but `stop` is checked before every instruction.
+    /// When it returns true the rest of `delta` is dropped, resuming later
+    /// carries on from that instruction without catching up.
+    pub fn run_until(&mut self, chip8: &mut Chip8, delta: Duration, mut stop: impl FnMut(&Chip8) -> bool) -> Result<bool, Chip8Error> {
+        self.elapsed += delta;
+        let elapsed = self.elapsed.as_nanos();
+        let due_instructions = (elapsed * u128::from(self.cpu_hz) / NANOS_PER_SEC) as u64;
+        let due_timer_ticks = (elapsed * u128::from(TIMER_HZ) / NANOS_PER_SEC) as u64;
+
+        let mut update_screen = false;
+        while self.instructions < due_instructions || self.timer_ticks < due_timer_ticks {
+            // The nth instruction happens at n / cpu_hz, the nth tick at n / TIMER_HZ
+            let next_instruction = u128::from(self.instructions + 1) * u128::from(TIMER_HZ);
+            let next_timer_tick = u128::from(self.timer_ticks + 1) * u128::from(self.cpu_hz);
+            let instruction_first = self.timer_ticks == due_timer_ticks
+                || (self.instructions < due_instructions && next_instruction <= next_timer_tick);
+
+            if instruction_first {
+                if stop(chip8) {
+                    // Rounded up so the instructions already run are still due
+                    let nanos = (u128::from(self.instructions) * NANOS_PER_SEC).div_ceil(u128::from(self.cpu_hz));
+                    self.elapsed = Duration::from_nanos(nanos as u64);
+                    break;
+                }
+                update_screen |= chip8.step()?;
+                self.instructions += 1;
+            } else {
+                chip8.tick_timers();
+                self.timer_ticks += 1;
+            }
+        }
+        Ok(update_screen)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // LD V0, 0xFF - LD DT, V0 - JP 0x204
+    const ROM: &[u8] = &[0x60, 0xFF, 0xF0, 0x15, 0x12, 0x04];
+
+    fn chip8() -> Chip8 {
+        let mut chip8 = Chip8::new();
+        chip8.load_rom(ROM).unwrap();
+        chip8
+    }
+
+    #[test]
+    fn run_for_one_second_test() {
+        let mut chip8 = chip8();
+        let mut scheduler = Scheduler::with_instructions_per_frame(10, 60);
+
+        scheduler.run_for(&mut chip8, Duration::from_secs(1)).unwrap();
+
+        assert_eq!(600, scheduler.instructions());
+        assert_eq!(60, scheduler.timer_ticks());
+        assert_eq!(0xFF - 60, chip8.cpu().dt);
+    }
+
+    #[test]
+    fn timers_do_not_depend_on_frame_rate_test() {
+        let mut chip8 = chip8();
+        let mut scheduler = Scheduler::new(1000, 20);
+
+        for _ in 0..20 {
+            scheduler.run_for(&mut chip8, scheduler.frame_duration()).unwrap();
+        }
+
+        assert_eq!(1000, scheduler.instructions());
+        assert_eq!(60, scheduler.timer_ticks());
+    }
+
+    #[test]
+    fn no_drift_with_uneven_frames_test() {
+        let mut chip8 = chip8();
+        let mut scheduler = Scheduler::new(700, 60);
+
+        // 7000 frames of 1/7 ms add up to exactly 1 second
+        for _ in 0..7000 {
+            scheduler.run_for(&mut chip8, Duration::from_nanos(142_857)).unwrap();
+        }
+        scheduler.run_for(&mut chip8, Duration::from_nanos(1_000)).unwrap();
+
+        assert_eq!(700, scheduler.instructions());
+        assert_eq!(60, scheduler.timer_ticks());
+    }
+
+    #[test]
+    fn run_until_drops_the_rest_test() {
+        let mut chip8 = chip8();
+        let mut scheduler = Scheduler::new(600, 60);
+
+        scheduler.run_until(&mut chip8, Duration::from_secs(1), |chip8| chip8.cpu().pc == 0x204).unwrap();
+        assert_eq!(2, scheduler.instructions());
+
+        // Carries on from the stop instead of catching up on the second dropped
+        scheduler.run_for(&mut chip8, Duration::from_millis(100)).unwrap();
+        assert_eq!(62, scheduler.instructions());
+    }
+}
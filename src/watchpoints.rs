@@ -0,0 +1,152 @@
+use std::fmt;
+
+use crate::cpu::MEMORY_SIZE;
+
+/// Kind of access a memory watchpoint reacts to.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Access {
+    Read,
+    Write,
+    ReadWrite
+}
+
+/// What a watchpoint is set on, memory ranges include both ends.
+/// Watchpoints on registers and I trigger on writes.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WatchTarget {
+    Memory { start: u16, end: u16 },
+    Register(u8),
+    I
+}
+
+/// A watchpoint that triggered, `pc` is the address of the instruction
+/// doing the access. For reads `old` and `new` are the same value.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct WatchHit {
+    pub pc: u16,
+    pub target: WatchTarget,
+    pub access: Access,
+    pub old: u16,
+    pub new: u16
+}
+
+impl fmt::Display for WatchHit {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let target = match self.target {
+            WatchTarget::Memory { start, .. } => format!("{:#05X}", start),
+            WatchTarget::Register(x) => format!("V{:X}", x),
+            WatchTarget::I => "I".to_string()
+        };
+        match self.access {
+            Access::Read => write!(f, "read {} = {:#04X} at {:#05X}", target, self.new, self.pc),
+            _ => write!(f, "write {} {:#04X} -> {:#04X} at {:#05X}", target, self.old, self.new, self.pc)
+        }
+    }
+}
+
+/// Watched addresses and registers, checked by the CPU on every access.
+///
+/// Memory is tracked with one bit per address so the set stays `Copy`
+/// like the rest of the machine state.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Watchpoints {
+    reads: [u64; MEMORY_SIZE / 64],
+    writes: [u64; MEMORY_SIZE / 64],
+    // One bit per V register
+    registers: u16,
+    i: bool
+}
+
+impl Watchpoints {
+    pub fn new() -> Self {
+        Watchpoints {
+            reads: [0; MEMORY_SIZE / 64],
+            writes: [0; MEMORY_SIZE / 64],
+            registers: 0,
+            i: false
+        }
+    }
+
+    pub fn add(&mut self, target: WatchTarget, access: Access) {
+        self.set(target, access, true);
+    }
+
+    /// Removes the watchpoints on `target`, whatever their access.
+    pub fn remove(&mut self, target: WatchTarget) {
+        self.set(target, Access::ReadWrite, false);
+    }
+
+    fn set(&mut self, target: WatchTarget, access: Access, watched: bool) {
+        match target {
+            WatchTarget::Memory { start, end } => {
+                for addr in usize::from(start)..=usize::from(end).min(MEMORY_SIZE - 1) {
+                    let (word, bit) = (addr / 64, 1 << (addr % 64));
+                    if access != Access::Write {
+                        set_bit(&mut self.reads[word], bit, watched);
+                    }
+                    if access != Access::Read {
+                        set_bit(&mut self.writes[word], bit, watched);
+                    }
+                }
+            }
+            WatchTarget::Register(x) => set_bit(&mut self.registers, 1 << (x & 0xF), watched),
+            WatchTarget::I => self.i = watched
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        *self == Self::new()
+    }
+
+    pub fn watches_read(&self, addr: usize) -> bool {
+        addr < MEMORY_SIZE && self.reads[addr / 64] & (1 << (addr % 64)) != 0
+    }
+
+    pub fn watches_write(&self, addr: usize) -> bool {
+        addr < MEMORY_SIZE && self.writes[addr / 64] & (1 << (addr % 64)) != 0
+    }
+
+    pub fn watches_register(&self, x: u8) -> bool {
+        self.registers & (1 << (x & 0xF)) != 0
+    }
+
+    pub fn watches_i(&self) -> bool {
+        self.i
+    }
+}
+
+impl Default for Watchpoints {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+fn set_bit<T>(bits: &mut T, bit: T, on: bool)
+where
+    T: Copy + std::ops::BitOr<Output = T> + std::ops::BitAnd<Output = T> + std::ops::Not<Output = T>
+{
+    *bits = if on { *bits | bit } else { *bits & !bit };
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn add_remove_test() {
+        let mut watchpoints = Watchpoints::new();
+        watchpoints.add(WatchTarget::Memory { start: 0x3A0, end: 0x3A3 }, Access::Write);
+        watchpoints.add(WatchTarget::Register(0xF), Access::Write);
+
+        assert!(watchpoints.watches_write(0x3A0));
+        assert!(watchpoints.watches_write(0x3A3));
+        assert!(!watchpoints.watches_write(0x3A4));
+        assert!(!watchpoints.watches_read(0x3A0));
+        assert!(watchpoints.watches_register(0xF));
+        assert!(!watchpoints.watches_i());
+
+        watchpoints.remove(WatchTarget::Memory { start: 0x3A0, end: 0x3A3 });
+        watchpoints.remove(WatchTarget::Register(0xF));
+        assert!(watchpoints.is_empty());
+    }
+}